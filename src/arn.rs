@@ -0,0 +1,80 @@
+use std::str::FromStr;
+
+use crate::error::Error;
+use crate::error::Error::InvalidArn;
+
+/// Amazon Resource Name split into its components
+pub struct Arn {
+    partition: String,
+    service: String,
+    account: String,
+    resource: String,
+}
+
+impl Arn {
+    pub fn partition(&self) -> &str {
+        &self.partition
+    }
+
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    pub fn account(&self) -> &str {
+        &self.account
+    }
+
+    pub fn resource(&self) -> &str {
+        &self.resource
+    }
+
+    /// Whether the ARN is the root user of its account, whose sessions are limited to one hour
+    pub fn is_root(&self) -> bool {
+        self.service == "iam" && self.resource == "root"
+    }
+}
+
+impl FromStr for Arn {
+    type Err = Error;
+
+    /// Parse `arn:{partition}:{service}:{region}:{account}:{resource}`
+    fn from_str(arn: &str) -> Result<Self, Self::Err> {
+        match arn.splitn(6, ':').collect::<Vec<&str>>()[..] {
+            ["arn", partition, service, _, account, resource]
+                if !partition.is_empty() && !service.is_empty() && !resource.is_empty() =>
+            {
+                Ok(Self {
+                    partition: String::from(partition),
+                    service: String::from(service),
+                    account: String::from(account),
+                    resource: String::from(resource),
+                })
+            }
+            _ => Err(InvalidArn(String::from(arn))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::arn::Arn;
+
+    #[tokio::test]
+    async fn test_parse_arn() -> Result<()> {
+        let arn = "arn:aws-us-gov:iam::123456789012:user/path/user_name".parse::<Arn>()?;
+        assert_eq!(arn.partition(), "aws-us-gov");
+        assert_eq!(arn.service(), "iam");
+        assert_eq!(arn.account(), "123456789012");
+        assert_eq!(arn.resource(), "user/path/user_name");
+        assert!(!arn.is_root());
+        assert!("arn:aws:iam::123456789012:root".parse::<Arn>()?.is_root());
+        assert!(!"arn:aws:sts::123456789012:root".parse::<Arn>()?.is_root());
+
+        assert!("arn:aws:iam".parse::<Arn>().is_err());
+        assert!("user/user_name".parse::<Arn>().is_err());
+
+        Ok(())
+    }
+}
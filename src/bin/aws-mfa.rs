@@ -1,10 +1,25 @@
-use anyhow::Result;
+use std::env::{temp_dir, var};
+use std::fs::{symlink_metadata, DirBuilder};
+use std::io::{stdin, ErrorKind, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
+use aws_config::retry::RetryMode;
 use clap::{Args, Parser, Subcommand};
+use serde_json::json;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
 
-use aws_mfa::{CredentialsProvider, EnvCredentialsProvider, FileCredentialsProvider};
+use aws_mfa::{
+    exec_command, exit_code, wait_child, AuthOptions, ClientOptions, Credentials,
+    CredentialsProvider, EnvCredentialsProvider, EnvFormat, FederationCredentialsProvider,
+    FederationOptions, FileCredentialsProvider, MfaCodeOptions, ProfileFiles, RetryOptions,
+    RoleCredentialsProvider, SeedEntry, SessionDuration, TimeoutOptions, AWS_MFA_KEY_FILE,
+};
 
-use crate::Command::{Env, File};
+use crate::Command::{Clean, Code, Env, Exec, Federate, File, Process, Role, Seed, Status};
 
 #[derive(Parser)]
 #[command(version, about)]
@@ -20,36 +35,248 @@ enum Command {
 
     /// Authenticate to AWS with MFA using environment variables
     Env(EnvArgs),
+
+    /// Assume a role with MFA using config and credentials files
+    Role(RoleArgs),
+
+    /// List sessions in the credentials file and their expiry
+    Status(StatusArgs),
+
+    /// Remove expired sessions from the credentials file
+    Clean(CleanArgs),
+
+    /// Print credentials in the credential_process JSON format using config and credentials files
+    Process(ProcessArgs),
+
+    /// Get credentials of a federated user scoped by session policies using config and credentials files
+    Federate(FederateArgs),
+
+    /// Print the current MFA code generated from the seed in the seed store or the credentials file
+    Code(CodeArgs),
+
+    /// Manage MFA seeds in the encrypted seed store
+    Seed(SeedArgs),
+
+    /// Run a command with credentials in its environment using config and credentials files
+    Exec(ExecArgs),
 }
 
 #[derive(Args)]
 struct AuthArgs {
-    /// MFA code
-    #[arg(short, long)]
-    code: String,
+    /// MFA code (defaults to a code fetched with --mfa-code-command, else generated from the seed store, `mfa_seed` in profile of credentials file or AWS_MFA_SEED, else fetched with `mfa_code_command` in profile, else prompted for)
+    #[arg(short, long, env = "AWS_MFA_CODE", hide_env_values = true)]
+    code: Option<String>,
+
+    /// Read the MFA code from the first line of stdin
+    #[arg(long)]
+    code_stdin: bool,
 
-    /// MFA device identifier (defaults to AWS username)
+    /// Command printing the MFA code, given the profile and MFA device ARN as JSON on stdin (defaults to `mfa_code_command` in profile)
+    #[arg(long, env = "AWS_MFA_CODE_COMMAND", value_name = "COMMAND")]
+    mfa_code_command: Option<String>,
+
+    /// MFA device identifier (defaults to the MFA device assigned to the AWS user)
     #[arg(short, long)]
     identifier: Option<String>,
 
-    /// Session duration in seconds
-    #[arg(short, long, default_value_t = 3600)]
-    duration: i32,
+    /// MFA device ARN (defaults to `mfa_serial` in profile)
+    #[arg(long, conflicts_with = "identifier")]
+    mfa_serial: Option<String>,
+
+    /// Session duration in seconds, with units like `12h` or `90m`, or `max` for the longest allowed (defaults to `duration_seconds` in profile or 3600)
+    #[arg(short, long)]
+    duration: Option<SessionDuration>,
 }
 
-#[derive(Args)]
-struct FileArgs {
-    #[command(flatten)]
-    auth: AuthArgs,
+fn choose_mfa_device(devices: &[String]) -> Option<String> {
+    if !stdin().is_terminal() {
+        return None;
+    }
+
+    eprintln!("Multiple MFA devices found:");
+    for (index, device) in devices.iter().enumerate() {
+        eprintln!("  [{}] {device}", index + 1);
+    }
+
+    loop {
+        eprint!("Choose an MFA device [1-{}]: ", devices.len());
+
+        let mut input = String::new();
+        if stdin().read_line(&mut input).ok()? == 0 {
+            return None;
+        }
+
+        match input.trim().parse::<usize>() {
+            Ok(index) if (1..=devices.len()).contains(&index) => {
+                return Some(devices[index - 1].clone())
+            }
+            _ => eprintln!("Invalid choice."),
+        }
+    }
+}
+
+/// Whether hidden prompts can be answered, since they read from the controlling terminal even when stdin is piped
+#[cfg(unix)]
+fn has_tty() -> bool {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .is_ok()
+}
+
+#[cfg(not(unix))]
+fn has_tty() -> bool {
+    stdin().is_terminal()
+}
+
+fn prompt_mfa_code(message: &str) -> Option<String> {
+    if !has_tty() {
+        return None;
+    }
+
+    rpassword::prompt_password(format!("{message}: "))
+        .ok()
+        .map(|code| String::from(code.trim()))
+        .filter(|code| !code.is_empty())
+}
+
+fn prompt_passphrase(message: &str) -> Option<String> {
+    if !has_tty() {
+        return None;
+    }
+
+    rpassword::prompt_password(format!("{message}: "))
+        .ok()
+        .filter(|passphrase| !passphrase.is_empty())
+}
+
+impl AuthArgs {
+    fn code(&self) -> Result<Option<String>> {
+        if !self.code_stdin {
+            return Ok(self.code.clone());
+        }
+
+        let mut input = String::new();
+        stdin().read_line(&mut input)?;
 
+        Ok(Some(String::from(input.trim())))
+    }
+
+    fn options(&self, files: &ProfileFiles) -> Result<AuthOptions> {
+        Ok(AuthOptions::new(
+            MfaCodeOptions::new(
+                self.code()?,
+                self.mfa_code_command.clone(),
+                Some(prompt_mfa_code),
+            ),
+            self.identifier.clone(),
+            self.mfa_serial.clone(),
+            self.duration,
+            Some(choose_mfa_device),
+            Some(prompt_passphrase),
+            Some(String::from(files.used_codes())),
+        ))
+    }
+}
+
+#[derive(Args)]
+struct FilesArgs {
     /// Home directory containing the AWS hidden folder
     #[arg(env = "HOME")]
     home: String,
 
+    /// Path of the AWS config file (defaults to ~/.aws/config)
+    #[arg(long, env = "AWS_CONFIG_FILE")]
+    config_file: Option<String>,
+
+    /// Path of the AWS credentials file (defaults to ~/.aws/credentials)
+    #[arg(long, env = "AWS_SHARED_CREDENTIALS_FILE")]
+    credentials_file: Option<String>,
+
+    /// Path of the encrypted seed store (defaults to ~/.config/aws-mfa/seeds.json)
+    #[arg(long, env = "AWS_MFA_SEED_STORE")]
+    seed_store: Option<String>,
+}
+
+impl FilesArgs {
+    fn files(&self) -> ProfileFiles {
+        ProfileFiles::new(
+            &self.home,
+            self.config_file.clone(),
+            self.credentials_file.clone(),
+            self.seed_store.clone(),
+        )
+    }
+}
+
+#[derive(Args)]
+struct ClientArgs {
     /// Name of the AWS region
     #[arg(short, long, env = "AWS_REGION")]
     region: Option<String>,
 
+    /// URL of the STS endpoint (defaults to `endpoint_url` in profile, AWS_ENDPOINT_URL_STS or AWS_ENDPOINT_URL)
+    #[arg(long)]
+    endpoint_url: Option<String>,
+
+    /// Use FIPS endpoints (defaults to `use_fips_endpoint` in profile or AWS_USE_FIPS_ENDPOINT)
+    #[arg(long)]
+    use_fips_endpoint: bool,
+
+    /// Use dual-stack endpoints (defaults to `use_dualstack_endpoint` in profile or AWS_USE_DUALSTACK_ENDPOINT)
+    #[arg(long)]
+    use_dualstack_endpoint: bool,
+
+    /// Timeout in seconds to connect to AWS (defaults to `connect_timeout` in profile)
+    #[arg(long, value_name = "SECONDS")]
+    connect_timeout: Option<u64>,
+
+    /// Timeout in seconds to read a response from AWS (defaults to `read_timeout` in profile)
+    #[arg(long, value_name = "SECONDS")]
+    read_timeout: Option<u64>,
+
+    /// Timeout in seconds of each AWS call, including retries (defaults to `operation_timeout` in profile)
+    #[arg(long, value_name = "SECONDS")]
+    operation_timeout: Option<u64>,
+
+    /// Maximum number of attempts of each AWS call (defaults to `max_attempts` in profile, AWS_MAX_ATTEMPTS or 3)
+    #[arg(long)]
+    max_attempts: Option<u32>,
+
+    /// Retry mode of AWS calls, standard or adaptive (defaults to `retry_mode` in profile, AWS_RETRY_MODE or standard)
+    #[arg(long)]
+    retry_mode: Option<RetryMode>,
+}
+
+impl ClientArgs {
+    fn options(&self) -> ClientOptions {
+        ClientOptions::new(
+            self.region.clone(),
+            self.endpoint_url.clone(),
+            self.use_fips_endpoint.then_some(true),
+            self.use_dualstack_endpoint.then_some(true),
+            TimeoutOptions::new(
+                self.connect_timeout,
+                self.read_timeout,
+                self.operation_timeout,
+            ),
+            RetryOptions::new(self.max_attempts, self.retry_mode),
+        )
+    }
+}
+
+#[derive(Args)]
+struct FileArgs {
+    #[command(flatten)]
+    auth: AuthArgs,
+
+    #[command(flatten)]
+    files: FilesArgs,
+
+    #[command(flatten)]
+    client: ClientArgs,
+
     /// Name of the AWS profile
     #[arg(short, long, default_value = "default", env = "AWS_PROFILE")]
     profile: String,
@@ -65,102 +292,738 @@ struct FileArgs {
 
 #[derive(Args)]
 struct EnvArgs {
+    /// Home directory containing the state of used MFA codes
+    #[arg(env = "HOME")]
+    home: String,
+
     #[command(flatten)]
     auth: AuthArgs,
+
+    #[command(flatten)]
+    client: ClientArgs,
+
+    #[command(flatten)]
+    format: FormatArgs,
+}
+
+#[derive(Args)]
+struct FormatArgs {
+    /// Output format, one of bash, zsh, fish, powershell, nushell, cmd, dotenv, json or github-actions (defaults to the format of the parent shell)
+    #[arg(long, value_name = "FORMAT")]
+    format: Option<EnvFormat>,
+}
+
+impl FormatArgs {
+    fn format(&self) -> Result<EnvFormat> {
+        Ok(self.format.unwrap_or_else(EnvFormat::detect).validate()?)
+    }
+}
+
+#[derive(Args)]
+struct RoleArgs {
+    #[command(flatten)]
+    file: FileArgs,
+}
+
+#[derive(Args)]
+struct FederateArgs {
+    #[command(flatten)]
+    files: FilesArgs,
+
+    #[command(flatten)]
+    client: ClientArgs,
+
+    /// Name of the federated user
+    #[arg(short, long)]
+    name: String,
+
+    /// Path of a JSON file with an inline session policy
+    #[arg(long, value_name = "FILE")]
+    policy: Option<String>,
+
+    /// ARN of a managed policy to scope the session with, can be repeated
+    #[arg(long = "policy-arn", value_name = "ARN")]
+    policy_arns: Vec<String>,
+
+    /// Session duration in seconds, with units like `12h` or `90m`, or `max` for the longest allowed (defaults to `duration_seconds` in profile or 3600)
+    #[arg(short, long)]
+    duration: Option<SessionDuration>,
+
+    /// Name of the AWS profile
+    #[arg(short, long, default_value = "default", env = "AWS_PROFILE")]
+    profile: String,
+
+    /// Suffix of the original AWS profile, whose long-term credentials are used as the caller
+    #[arg(short, long, default_value = "noauth")]
+    suffix: String,
+
+    /// Save credentials under this profile in the credentials file instead of printing them as environment variables
+    #[arg(short, long, value_name = "PROFILE", conflicts_with = "format")]
+    output: Option<String>,
+
+    /// Force authentication even though current credentials are still valid
+    #[arg(short, long, requires = "output")]
+    force: bool,
+
+    #[command(flatten)]
+    format: FormatArgs,
+}
+
+#[derive(Args)]
+struct CodeArgs {
+    #[command(flatten)]
+    files: FilesArgs,
+
+    /// Name of the AWS profile
+    #[arg(short, long, default_value = "default", env = "AWS_PROFILE")]
+    profile: String,
+
+    /// Suffix of the original AWS profile
+    #[arg(short, long, default_value = "noauth")]
+    suffix: String,
+}
+
+#[derive(Args)]
+struct SeedArgs {
+    #[command(flatten)]
+    files: FilesArgs,
+
+    #[command(subcommand)]
+    command: SeedCommand,
+}
+
+#[derive(Subcommand)]
+enum SeedCommand {
+    /// Add or replace the seed of a profile, read from a hidden prompt or stdin
+    Add(SeedAddArgs),
+
+    /// Import the seed of an MFA device from an otpauth:// URI, a Base32 seed or a PNG or JPEG image of its QR code
+    Import(SeedImportArgs),
+
+    /// List profiles with a seed and their TOTP settings, without the seeds
+    List,
+
+    /// Remove the seed of a profile
+    Remove(SeedProfileArgs),
+
+    /// Move plaintext `mfa_seed` settings of the credentials file into the seed store
+    Migrate,
+
+    /// Encrypt the seed store with a new passphrase
+    Rekey,
+
+    /// Cache the key of the seed store for the shell session, to be evaluated like `eval "$(aws-mfa seed unlock)"`
+    Unlock(FormatArgs),
+
+    /// Delete the cached key of the seed store, to be evaluated like `eval "$(aws-mfa seed lock)"`
+    Lock(FormatArgs),
+}
+
+#[derive(Args)]
+struct SeedProfileArgs {
+    /// Name of the AWS profile
+    #[arg(short, long, default_value = "default", env = "AWS_PROFILE")]
+    profile: String,
+
+    /// Suffix of the original AWS profile
+    #[arg(short, long, default_value = "noauth")]
+    suffix: String,
+}
+
+#[derive(Args)]
+struct SeedAddArgs {
+    #[command(flatten)]
+    profile: SeedProfileArgs,
+
+    /// HMAC algorithm of the MFA device, SHA1, SHA256 or SHA512 (defaults to SHA1)
+    #[arg(long)]
+    algorithm: Option<String>,
+
+    /// Number of digits of MFA codes, 6 or 8 (defaults to 6)
+    #[arg(long)]
+    digits: Option<u32>,
+
+    /// Period in seconds of MFA codes (defaults to 30)
+    #[arg(long)]
+    period: Option<u64>,
+
+    /// MFA device ARN the seed belongs to
+    #[arg(long)]
+    mfa_serial: Option<String>,
+}
+
+#[derive(Args)]
+struct SeedImportArgs {
+    #[command(flatten)]
+    profile: SeedProfileArgs,
+
+    /// otpauth:// URI, Base32 seed, or path of a QR code image (defaults to a hidden prompt or stdin)
+    input: Option<String>,
+
+    /// MFA device ARN the seed belongs to, saved as `mfa_serial` in profile (defaults to `mfa_serial` in profile)
+    #[arg(long)]
+    mfa_serial: Option<String>,
+}
+
+#[derive(Args)]
+struct ExecArgs {
+    #[command(flatten)]
+    file: FileArgs,
+
+    /// Command to run, with its arguments
+    #[arg(last = true, required = true, value_name = "COMMAND")]
+    command: Vec<String>,
+}
+
+#[derive(Args)]
+struct StatusArgs {
+    #[command(flatten)]
+    files: FilesArgs,
+
+    /// Suffix of the original AWS profile
+    #[arg(short, long, default_value = "noauth")]
+    suffix: String,
+
+    /// Print sessions as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct CleanArgs {
+    #[command(flatten)]
+    files: FilesArgs,
+
+    /// Suffix of the original AWS profile
+    #[arg(short, long, default_value = "noauth")]
+    suffix: String,
+
+    /// Remove still valid sessions as well
+    #[arg(short, long)]
+    all: bool,
+
+    /// List sessions that would be removed without removing them
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Args)]
+struct ProcessArgs {
+    #[command(flatten)]
+    file: FileArgs,
 }
 
 #[async_trait]
-trait Authenticate {
-    async fn authenticate(&self) -> Result<()>;
+trait Execute {
+    async fn execute(&self) -> Result<()>;
 }
 
 impl Cli {
-    fn args(self) -> Box<dyn Authenticate> {
+    fn args(self) -> Box<dyn Execute> {
         match self.command {
             File(args) => Box::new(args),
             Env(args) => Box::new(args),
+            Role(args) => Box::new(args),
+            Status(args) => Box::new(args),
+            Clean(args) => Box::new(args),
+            Process(args) => Box::new(args),
+            Federate(args) => Box::new(args),
+            Code(args) => Box::new(args),
+            Seed(args) => Box::new(args),
+            Exec(args) => Box::new(args),
         }
     }
 }
 
-#[async_trait]
-impl Authenticate for FileArgs {
-    async fn authenticate(&self) -> Result<()> {
-        let provider = FileCredentialsProvider::new(
-            &self.auth.code,
-            &self.home,
-            self.region.clone(),
+impl FileArgs {
+    fn provider(&self) -> Result<FileCredentialsProvider> {
+        let files = self.files.files();
+        Ok(FileCredentialsProvider::new(
+            self.auth.options(&files)?,
+            files,
+            self.client.options(),
             &self.profile,
             &self.suffix,
-            self.auth.identifier.clone(),
-            self.auth.duration,
-        );
+        ))
+    }
+}
 
-        if !self.force {
-            if let Some(credentials) = provider.validate().await? {
-                println!(
-                    "Current credentials are still valid and will expire in {}.",
-                    credentials.session_duration()?
-                );
-                println!("Use --force or -f to authenticate anyway.");
+async fn authenticate(provider: &(impl CredentialsProvider + Sync), force: bool) -> Result<()> {
+    if !force {
+        if let Some(credentials) = provider.validate().await? {
+            println!(
+                "Current credentials are still valid and will expire in {}.",
+                credentials.session_duration()?
+            );
+            println!("Use --force or -f to authenticate anyway.");
 
-                return Ok(());
-            }
+            return Ok(());
         }
+    }
 
-        println!("Authenticating...");
+    println!("Authenticating...");
 
-        let credentials = provider.authenticate().await?;
+    let credentials = provider.authenticate().await?;
 
-        println!("Authentication successful!");
-        println!(
-            "New credentials will expire in {}.",
-            credentials.session_duration()?
-        );
+    println!("Authentication successful!");
+    println!(
+        "New credentials will expire in {}.",
+        credentials.session_duration()?
+    );
 
-        Ok(())
+    Ok(())
+}
+
+#[async_trait]
+impl Execute for FileArgs {
+    async fn execute(&self) -> Result<()> {
+        authenticate(&self.provider()?, self.force).await
+    }
+}
+
+impl RoleArgs {
+    fn provider(&self) -> Result<RoleCredentialsProvider> {
+        let files = self.file.files.files();
+        Ok(RoleCredentialsProvider::new(
+            self.file.auth.options(&files)?,
+            files,
+            self.file.client.options(),
+            &self.file.profile,
+            &self.file.suffix,
+        ))
     }
 }
 
 #[async_trait]
-impl Authenticate for EnvArgs {
-    async fn authenticate(&self) -> Result<()> {
-        let provider = EnvCredentialsProvider::new(
-            &self.auth.code,
-            self.auth.identifier.clone(),
-            self.auth.duration,
-        );
+impl Execute for RoleArgs {
+    async fn execute(&self) -> Result<()> {
+        authenticate(&self.provider()?, self.file.force).await
+    }
+}
+
+#[async_trait]
+impl Execute for EnvArgs {
+    async fn execute(&self) -> Result<()> {
+        let format = self.format.format()?;
+        let files = ProfileFiles::new(&self.home, None, None, None);
+        let provider =
+            EnvCredentialsProvider::new(self.auth.options(&files)?, self.client.options());
 
         if let Some(credentials) = provider.validate().await? {
-            println!(
-                "echo \"Current credentials are still valid and will expire in {}.\"",
+            eprintln!(
+                "Current credentials are still valid and will expire in {}.",
                 credentials.session_duration()?,
             );
 
             return Ok(());
         }
 
+        eprintln!("Authenticating...");
+
+        let credentials = provider.authenticate().await?;
+
+        print_env(&credentials, format)
+    }
+}
+
+fn print_env(credentials: &Credentials, format: EnvFormat) -> Result<()> {
+    let vars = [
+        ("AWS_ACCESS_KEY_ID", credentials.access_key_id().to_string()),
+        (
+            "AWS_SECRET_ACCESS_KEY",
+            credentials.secret_access_key().to_string(),
+        ),
+        ("AWS_SESSION_TOKEN", credentials.session_token().to_string()),
+        (
+            "AWS_SESSION_EXPIRATION_TIMESTAMP",
+            credentials.session_expiration_timestamp().to_string(),
+        ),
+    ];
+    print!("{}", format.export(&vars)?);
+
+    eprintln!("Authentication successful!");
+    eprintln!(
+        "New credentials will expire in {}.",
+        credentials.session_duration()?
+    );
+
+    Ok(())
+}
+
+impl FederateArgs {
+    fn provider(&self) -> FederationCredentialsProvider {
+        FederationCredentialsProvider::new(
+            FederationOptions::new(
+                &self.name,
+                self.policy.clone(),
+                self.policy_arns.clone(),
+                self.duration,
+            ),
+            self.files.files(),
+            self.client.options(),
+            &format!("{}-{}", self.profile, self.suffix),
+            self.output.clone(),
+        )
+    }
+}
+
+#[async_trait]
+impl Execute for FederateArgs {
+    async fn execute(&self) -> Result<()> {
+        let provider = self.provider();
+
+        if self.output.is_some() {
+            return authenticate(&provider, self.force).await;
+        }
+
+        let format = self.format.format()?;
+
         let credentials = provider.authenticate().await?;
 
+        print_env(&credentials, format)
+    }
+}
+
+#[async_trait]
+impl Execute for CodeArgs {
+    async fn execute(&self) -> Result<()> {
+        let code =
+            self.files
+                .files()
+                .mfa_code(&self.profile, &self.suffix, Some(prompt_passphrase))?;
+
+        println!("{}", code.code());
+        eprintln!("Valid for {}.", code.remaining());
+
+        Ok(())
+    }
+}
+
+impl SeedProfileArgs {
+    fn name(&self) -> String {
+        format!("{}-{}", self.profile, self.suffix)
+    }
+}
+
+fn read_seed(message: &str) -> Result<String> {
+    let seed = match stdin().is_terminal() {
+        true => rpassword::prompt_password(format!("{message}: "))?,
+        false => {
+            let mut input = String::new();
+            stdin().read_line(&mut input)?;
+            input
+        }
+    };
+
+    Ok(String::from(seed.trim()))
+}
+
+/// Key file caching the key of the seed store, in a directory only the user can access
+///
+/// The directory is `aws-mfa` in the user's runtime directory, else `aws-mfa-{uid}` in the temporary directory,
+/// and the key file is kept until `aws-mfa seed lock` or until the directory is cleared.
+fn key_file() -> Result<PathBuf> {
+    let dir = match var("XDG_RUNTIME_DIR").ok().filter(|d| !d.is_empty()) {
+        Some(dir) => PathBuf::from(dir).join("aws-mfa"),
+        None => temp_dir().join(private_dir_name()),
+    };
+    create_private_dir(&dir)
+        .with_context(|| format!("Could not create private directory {}", dir.display()))?;
+
+    Ok(dir.join("seeds.key"))
+}
+
+#[cfg(unix)]
+fn private_dir_name() -> String {
+    format!("aws-mfa-{}", unsafe { libc::getuid() })
+}
+
+#[cfg(not(unix))]
+fn private_dir_name() -> String {
+    String::from("aws-mfa")
+}
+
+/// Create `dir` with owner-only permissions, checking that an existing one is owned by the user and accessible only to them
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    if let Err(e) = DirBuilder::new().mode(0o700).create(dir) {
+        if e.kind() != ErrorKind::AlreadyExists {
+            return Err(e.into());
+        }
+    }
+
+    let metadata = symlink_metadata(dir)?;
+    if !metadata.is_dir()
+        || metadata.uid() != unsafe { libc::getuid() }
+        || metadata.mode() & 0o077 != 0
+    {
+        bail!("Directory is not owned by the user or is accessible to others");
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> Result<()> {
+    Ok(DirBuilder::new().recursive(true).create(dir)?)
+}
+
+#[async_trait]
+impl Execute for SeedArgs {
+    async fn execute(&self) -> Result<()> {
+        let store = self.files.files().seed_store(Some(prompt_passphrase));
+
+        match &self.command {
+            SeedCommand::Add(args) => {
+                let entry = SeedEntry::new(
+                    &args.profile.name(),
+                    &read_seed("MFA seed")?,
+                    args.algorithm.clone(),
+                    args.digits,
+                    args.period,
+                    args.mfa_serial.clone(),
+                    None,
+                );
+                store.add(entry)?;
+                eprintln!("Seed of profile `{}` saved.", args.profile.name());
+            }
+            SeedCommand::Import(args) => {
+                let input = match &args.input {
+                    Some(input) => input.clone(),
+                    None => read_seed("MFA seed or otpauth:// URI")?,
+                };
+                let entry = self.files.files().import_seed(
+                    &args.profile.profile,
+                    &args.profile.suffix,
+                    &input,
+                    args.mfa_serial.clone(),
+                    Some(prompt_passphrase),
+                )?;
+
+                eprintln!(
+                    "Seed{} imported for profile `{}`.",
+                    entry
+                        .issuer()
+                        .map(|issuer| format!(" of {issuer}"))
+                        .unwrap_or_default(),
+                    entry.profile()
+                );
+                match entry.mfa_serial() {
+                    Some(arn) => eprintln!("Linked to MFA device {arn}."),
+                    None => eprintln!("Use --mfa-serial to link it to its MFA device."),
+                }
+            }
+            SeedCommand::List => {
+                let entries = store.entries()?;
+                if entries.is_empty() {
+                    println!("No seeds found.");
+                }
+
+                for entry in entries {
+                    let settings = [
+                        ("issuer", entry.issuer()),
+                        ("algorithm", entry.algorithm()),
+                        ("digits", entry.digits()),
+                        ("period", entry.period()),
+                        ("mfa_serial", entry.mfa_serial()),
+                    ]
+                    .into_iter()
+                    .filter_map(|(key, value)| value.map(|v| format!("{key} {v}")))
+                    .collect::<Vec<_>>();
+
+                    match settings.is_empty() {
+                        true => println!("{}", entry.profile()),
+                        false => println!("{} ({})", entry.profile(), settings.join(", ")),
+                    }
+                }
+            }
+            SeedCommand::Remove(args) => match store.remove(&args.name())? {
+                true => eprintln!("Seed of profile `{}` removed.", args.name()),
+                false => eprintln!("No seed found for profile `{}`.", args.name()),
+            },
+            SeedCommand::Migrate => {
+                let (moved, kept) = self.files.files().migrate_seeds(Some(prompt_passphrase))?;
+                if moved.is_empty() && kept.is_empty() {
+                    eprintln!("No plaintext seeds found in credentials file.");
+                }
+
+                for profile in moved {
+                    eprintln!("Seed of profile `{profile}` moved to the seed store.");
+                }
+                for profile in kept {
+                    eprintln!("Seed of profile `{profile}` already in the seed store, removed from credentials file.");
+                }
+            }
+            SeedCommand::Rekey => {
+                store.rekey()?;
+                eprintln!("Seed store encrypted with the new passphrase.");
+            }
+            SeedCommand::Unlock(format) => {
+                let format = format.format()?;
+                let key_file = key_file()?.to_string_lossy().to_string();
+                store.unlock(&key_file)?;
+                print!("{}", format.export(&[(AWS_MFA_KEY_FILE, key_file)])?);
+                eprintln!(
+                    "Seed store unlocked for this shell session, lock it with `aws-mfa seed lock`."
+                );
+            }
+            SeedCommand::Lock(format) => {
+                let format = format.format()?;
+                let key_file = match var(AWS_MFA_KEY_FILE).ok().filter(|f| !f.is_empty()) {
+                    Some(key_file) => key_file,
+                    None => key_file()?.to_string_lossy().to_string(),
+                };
+                match store.lock(&key_file)? {
+                    true => eprintln!("Seed store locked."),
+                    false => eprintln!("Seed store already locked."),
+                }
+                print!("{}", format.unset(&[AWS_MFA_KEY_FILE])?);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Execute for StatusArgs {
+    async fn execute(&self) -> Result<()> {
+        let sessions = self.files.files().sessions(&self.suffix)?;
+
+        if self.json {
+            let sessions = sessions
+                .iter()
+                .map(|s| {
+                    Ok(json!({
+                        "profile": s.profile(),
+                        "source_profile": s.source_profile(),
+                        "expiration_timestamp": s.credentials().session_expiration_timestamp(),
+                        "remaining_seconds": s.credentials().session_duration()?.whole_seconds().max(0),
+                        "expired": s.credentials().expired(),
+                    }))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            println!("{}", serde_json::to_string_pretty(&sessions)?);
+
+            return Ok(());
+        }
+
+        if sessions.is_empty() {
+            println!("No sessions found.");
+
+            return Ok(());
+        }
+
+        for session in sessions {
+            let status = if session.credentials().expired() {
+                String::from("expired")
+            } else {
+                format!("expires in {}", session.credentials().session_duration()?)
+            };
+
+            match session.source_profile() {
+                Some(source_profile) => println!(
+                    "{}: {status} (source profile {source_profile})",
+                    session.profile()
+                ),
+                None => println!("{}: {status}", session.profile()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Execute for CleanArgs {
+    async fn execute(&self) -> Result<()> {
+        let sessions = self
+            .files
+            .files()
+            .clean_sessions(&self.suffix, self.all, self.dry_run)?;
+
+        if sessions.is_empty() {
+            println!("No sessions to remove.");
+
+            return Ok(());
+        }
+
+        let action = if self.dry_run {
+            "Would remove"
+        } else {
+            "Removed"
+        };
+        for session in sessions {
+            let status = if session.credentials().expired() {
+                "expired"
+            } else {
+                "valid"
+            };
+            println!("{action} {status} session `{}`.", session.profile());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Execute for ProcessArgs {
+    async fn execute(&self) -> Result<()> {
+        let provider = self.file.provider()?;
+
+        let credentials = match provider.validate().await? {
+            Some(credentials) if !self.file.force => credentials,
+            _ => {
+                eprintln!("Authenticating...");
+                provider.authenticate().await?
+            }
+        };
+
+        let expiration =
+            OffsetDateTime::from_unix_timestamp(credentials.session_expiration_timestamp())?
+                .format(&Rfc3339)?;
+
         println!(
-            "export AWS_ACCESS_KEY_ID=\"{}\"
-export AWS_SECRET_ACCESS_KEY=\"{}\"
-export AWS_SESSION_TOKEN=\"{}\"
-export AWS_SESSION_EXPIRATION_TIMESTAMP=\"{}\" && \
-echo \"Authentication successful!\" && \
-echo \"New credentials will expire in {}.\"",
-            credentials.access_key_id(),
-            credentials.secret_access_key(),
-            credentials.session_token(),
-            credentials.session_expiration_timestamp(),
-            credentials.session_duration()?,
+            "{}",
+            json!({
+                "Version": 1,
+                "AccessKeyId": credentials.access_key_id(),
+                "SecretAccessKey": credentials.secret_access_key(),
+                "SessionToken": credentials.session_token(),
+                "Expiration": expiration,
+            })
         );
 
         Ok(())
     }
 }
 
+#[async_trait]
+impl Execute for ExecArgs {
+    async fn execute(&self) -> Result<()> {
+        let provider = self.file.provider()?;
+
+        let credentials = match provider.validate().await? {
+            Some(credentials) if !self.file.force => credentials,
+            _ => {
+                eprintln!("Authenticating...");
+                provider.authenticate().await?
+            }
+        };
+        let region = provider.region().await?;
+
+        let mut child = exec_command(&self.command, &credentials, region.as_deref())?
+            .spawn()
+            .with_context(|| format!("could not run command `{}`", self.command[0]))?;
+        let status = wait_child(&mut child).await?;
+
+        exit(exit_code(status))
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    Cli::parse().args().authenticate().await
+    Cli::parse().args().execute().await
 }
@@ -0,0 +1,158 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+use crate::check_code;
+use crate::error::Error;
+use crate::error::Error::{
+    CodeCommandFailed, CodeCommandTimeout, InvalidCodeCommandOutput, RunCodeCommandError,
+};
+
+const CODE_COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// External command printing an MFA code on stdout, configured with `mfa_code_command`
+///
+/// The command runs in a shell and receives the profile and MFA device ARN as a JSON object on stdin,
+/// like `{"profile":"default","mfa_serial":"arn:aws:iam::123456789012:mfa/device"}`.
+/// Its stderr is inherited, so that interactive providers can ask the user to unlock or touch a device.
+pub struct CodeCommand {
+    command: String,
+    profile: Option<String>,
+    timeout: Duration,
+}
+
+impl CodeCommand {
+    pub fn new(command: &str, profile: Option<&str>) -> Self {
+        Self {
+            command: String::from(command),
+            profile: profile.map(String::from),
+            timeout: CODE_COMMAND_TIMEOUT,
+        }
+    }
+
+    fn shell(&self) -> Command {
+        let (shell, flag) = match cfg!(windows) {
+            true => ("cmd", "/C"),
+            false => ("sh", "-c"),
+        };
+        let mut command = Command::new(shell);
+        command.args([flag, self.command.as_str()]);
+
+        command
+    }
+
+    /// Run the command for the MFA device `mfa_serial` and return the code it printed, killing it after the timeout
+    pub async fn run(&self, mfa_serial: &str) -> Result<String, Error> {
+        let run_error = |e| RunCodeCommandError {
+            command: self.command.clone(),
+            source: e,
+        };
+        let context = json!({
+            "profile": self.profile,
+            "mfa_serial": mfa_serial,
+        });
+
+        let mut child = self
+            .shell()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(run_error)?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            // Commands ignoring the context may exit before reading it, which is not an error
+            let _ = stdin.write_all(context.to_string().as_bytes()).await;
+        }
+
+        let output = timeout(self.timeout, child.wait_with_output())
+            .await
+            .map_err(|_| CodeCommandTimeout {
+                command: self.command.clone(),
+                timeout: self.timeout.as_secs(),
+            })?
+            .map_err(run_error)?;
+
+        if !output.status.success() {
+            return Err(CodeCommandFailed {
+                command: self.command.clone(),
+                status: output.status.to_string(),
+            });
+        }
+
+        let code = String::from(String::from_utf8_lossy(&output.stdout).trim());
+        check_code(&code).map_err(|_| InvalidCodeCommandOutput(self.command.clone()))?;
+
+        Ok(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+    use std::time::Duration;
+
+    use crate::command::CodeCommand;
+    use crate::error::Error::{CodeCommandFailed, CodeCommandTimeout, InvalidCodeCommandOutput};
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_code_command() -> Result<()> {
+        let dir = temp_dir().join(format!("aws-mfa-test-code-command-{}", std::process::id()));
+        create_dir_all(&dir)?;
+        let script = dir.join("code.sh");
+        let context = dir.join("context.json");
+        write(
+            &script,
+            format!("cat > '{}'\necho ' 123456 '\n", context.to_string_lossy()),
+        )?;
+
+        let command = CodeCommand::new(&format!("sh '{}'", script.to_string_lossy()), Some("dev"));
+        assert_eq!(
+            command.run("arn:aws:iam::123456789012:mfa/device").await?,
+            "123456"
+        );
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&read_to_string(&context)?)?,
+            serde_json::json!({
+                "profile": "dev",
+                "mfa_serial": "arn:aws:iam::123456789012:mfa/device",
+            })
+        );
+
+        assert_eq!(
+            CodeCommand::new("echo 654321", None).run("arn").await?,
+            "654321"
+        );
+
+        match CodeCommand::new("exit 3", None).run("arn").await {
+            Err(CodeCommandFailed { status, .. }) => assert!(status.contains('3')),
+            _ => panic!("expected CodeCommandFailed"),
+        }
+
+        assert!(matches!(
+            CodeCommand::new("echo code", None).run("arn").await,
+            Err(InvalidCodeCommandOutput(_))
+        ));
+
+        let command = CodeCommand {
+            timeout: Duration::from_millis(100),
+            ..CodeCommand::new("sleep 5", None)
+        };
+        assert!(matches!(
+            command.run("arn").await,
+            Err(CodeCommandTimeout { .. })
+        ));
+
+        remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
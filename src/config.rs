@@ -1,50 +1,475 @@
 use aws_config::environment::EnvironmentVariableCredentialsProvider;
 use aws_config::profile::ProfileFileCredentialsProvider;
-use aws_config::{from_env, SdkConfig};
+use aws_config::retry::{RetryConfig, RetryMode};
+use aws_config::timeout::TimeoutConfig;
+use aws_config::{from_env, ConfigLoader, SdkConfig};
 use aws_runtime::env_config::file::EnvConfigFileKind::{Config, Credentials};
 use aws_runtime::env_config::file::EnvConfigFiles;
 use aws_sdk_sts::config::Region;
+use std::env::var;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
 
-fn get_config_file(home: &str) -> String {
-    format!("{home}/.aws/config")
+use crate::error::Error;
+use crate::error::Error::{InvalidProfileSetting, MissingProfileSetting};
+use crate::ini::Document;
+use crate::io::{find_config_value, read_config_file, save_config_value};
+use crate::{ClientOptions, ProfileFiles, RetryOptions, TimeoutOptions};
+
+pub const ROLE_ARN: &str = "role_arn";
+pub const SOURCE_PROFILE: &str = "source_profile";
+pub const MFA_SERIAL: &str = "mfa_serial";
+pub const MFA_DEVICE_PREFERENCE: &str = "mfa_device_preference";
+pub const EXTERNAL_ID: &str = "external_id";
+pub const ROLE_SESSION_NAME: &str = "role_session_name";
+pub const DURATION_SECONDS: &str = "duration_seconds";
+pub const MFA_SEED: &str = "mfa_seed";
+pub const MFA_ALGORITHM: &str = "mfa_algorithm";
+pub const MFA_DIGITS: &str = "mfa_digits";
+pub const MFA_PERIOD: &str = "mfa_period";
+pub const MFA_CODE_COMMAND: &str = "mfa_code_command";
+pub const CONNECT_TIMEOUT: &str = "connect_timeout";
+pub const READ_TIMEOUT: &str = "read_timeout";
+pub const OPERATION_TIMEOUT: &str = "operation_timeout";
+pub const MAX_ATTEMPTS: &str = "max_attempts";
+pub const RETRY_MODE: &str = "retry_mode";
+
+const AWS_CONFIG_FILE: &str = "AWS_CONFIG_FILE";
+const AWS_SHARED_CREDENTIALS_FILE: &str = "AWS_SHARED_CREDENTIALS_FILE";
+const AWS_MFA_SEED_STORE: &str = "AWS_MFA_SEED_STORE";
+const XDG_CONFIG_HOME: &str = "XDG_CONFIG_HOME";
+const XDG_STATE_HOME: &str = "XDG_STATE_HOME";
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_millis(3100);
+
+/// Settings of profiles in the config file, looked up in order until a value is found
+pub struct ProfileSettings {
+    file_path: Option<String>,
+    document: Document,
+    profiles: Vec<String>,
 }
 
-pub fn get_credentials_file(home: &str) -> String {
-    format!("{home}/.aws/credentials")
+impl ProfileSettings {
+    pub fn load(files: &ProfileFiles, profiles: &[String]) -> Result<Self, Error> {
+        Ok(Self {
+            file_path: Some(String::from(files.config())),
+            document: read_config_file(files.config())?,
+            profiles: profiles.to_vec(),
+        })
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            file_path: None,
+            document: Document::parse(""),
+            profiles: Vec::new(),
+        }
+    }
+
+    /// Save `key` in the first profile of the config file, unless settings are empty, returning that profile
+    pub fn save(&self, key: &str, value: &str) -> Result<Option<&str>, Error> {
+        match (&self.file_path, self.profiles.first()) {
+            (Some(file_path), Some(profile)) => {
+                save_config_value(file_path, profile, key, value)?;
+                Ok(Some(profile))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn find(&self, key: &str) -> Option<(&str, &str)> {
+        self.profiles
+            .iter()
+            .find_map(|p| find_config_value(&self.document, p, key).map(|v| (p.as_str(), v)))
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.find(key).map(|(_, v)| String::from(v))
+    }
+
+    pub fn require(&self, key: &str) -> Result<String, Error> {
+        self.get(key).ok_or_else(|| MissingProfileSetting {
+            profile: self.profiles.first().cloned().unwrap_or_default(),
+            key: String::from(key),
+        })
+    }
+
+    pub fn parse<T: FromStr>(&self, key: &str) -> Result<Option<T>, Error> {
+        self.find(key)
+            .map(|(profile, value)| {
+                value.parse::<T>().map_err(|_| InvalidProfileSetting {
+                    profile: String::from(profile),
+                    key: String::from(key),
+                    value: String::from(value),
+                })
+            })
+            .transpose()
+    }
 }
 
-pub async fn get_file_config(
-    home: &str,
-    region: Option<String>,
-    profile: &str,
-    suffix: &str,
-) -> SdkConfig {
-    let files = EnvConfigFiles::builder()
-        .with_file(Config, get_config_file(home))
-        .with_file(Credentials, get_credentials_file(home))
-        .build();
+fn expand_home(home: &str, path: &str) -> String {
+    match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("{home}{rest}"),
+        _ => String::from(path),
+    }
+}
+
+fn resolve_file(home: &str, file: Option<String>, var_name: &str, default: &str) -> String {
+    match file.or_else(|| var(var_name).ok().filter(|f| !f.is_empty())) {
+        Some(file) => expand_home(home, &file),
+        None => format!("{home}/.aws/{default}"),
+    }
+}
 
-    let mut config = from_env()
-        .profile_files(files)
-        .profile_name(format!("{profile}-{suffix}"));
+pub fn get_config_file(home: &str, config_file: Option<String>) -> String {
+    resolve_file(home, config_file, AWS_CONFIG_FILE, "config")
+}
 
-    if let Some(region) = region {
-        config = config.region(Region::new(region));
+pub fn get_credentials_file(home: &str, credentials_file: Option<String>) -> String {
+    resolve_file(
+        home,
+        credentials_file,
+        AWS_SHARED_CREDENTIALS_FILE,
+        "credentials",
+    )
+}
+
+/// Seed store from an explicit path, then `AWS_MFA_SEED_STORE`, then `aws-mfa/seeds.json` in the user's config directory
+pub fn get_seed_store_file(home: &str, seed_store: Option<String>) -> String {
+    match seed_store.or_else(|| var(AWS_MFA_SEED_STORE).ok().filter(|f| !f.is_empty())) {
+        Some(file) => expand_home(home, &file),
+        None => match var(XDG_CONFIG_HOME).ok().filter(|d| !d.is_empty()) {
+            Some(dir) => format!("{dir}/aws-mfa/seeds.json"),
+            None => format!("{home}/.config/aws-mfa/seeds.json"),
+        },
+    }
+}
+
+/// State file of used MFA codes in `$XDG_STATE_HOME/aws-mfa`, then in `.local/state/aws-mfa` of the home directory
+pub fn get_used_codes_file(home: &str) -> String {
+    match var(XDG_STATE_HOME).ok().filter(|d| !d.is_empty()) {
+        Some(dir) => format!("{dir}/aws-mfa/codes.json"),
+        None => format!("{home}/.local/state/aws-mfa/codes.json"),
+    }
+}
+
+/// Files missing on disk are loaded as empty, since the SDK fails to load any profile otherwise
+fn get_profile_files(files: &ProfileFiles) -> EnvConfigFiles {
+    [(Config, files.config()), (Credentials, files.credentials())]
+        .into_iter()
+        .fold(
+            EnvConfigFiles::builder(),
+            |builder, (kind, file)| match Path::new(file).exists() {
+                true => builder.with_file(kind, file),
+                false => builder.with_contents(kind, ""),
+            },
+        )
+        .build()
+}
+
+/// Value from options, then from profile settings
+fn resolve<T: FromStr>(
+    option: Option<T>,
+    settings: &ProfileSettings,
+    key: &str,
+) -> Result<Option<T>, Error> {
+    match option {
+        Some(value) => Ok(Some(value)),
+        None => settings.parse(key),
+    }
+}
+
+/// Timeouts overriding the SDK defaults, keeping the default connect timeout unless set
+fn get_timeout_config(
+    timeouts: &TimeoutOptions,
+    settings: &ProfileSettings,
+) -> Result<Option<TimeoutConfig>, Error> {
+    let connect = resolve(timeouts.connect, settings, CONNECT_TIMEOUT)?;
+    let read = resolve(timeouts.read, settings, READ_TIMEOUT)?;
+    let operation = resolve(timeouts.operation, settings, OPERATION_TIMEOUT)?;
+    if connect.is_none() && read.is_none() && operation.is_none() {
+        return Ok(None);
+    }
+
+    let mut builder = TimeoutConfig::builder();
+    builder
+        .set_connect_timeout(Some(
+            connect.map_or(DEFAULT_CONNECT_TIMEOUT, Duration::from_secs),
+        ))
+        .set_read_timeout(read.map(Duration::from_secs))
+        .set_operation_timeout(operation.map(Duration::from_secs));
+
+    Ok(Some(builder.build()))
+}
+
+/// Retry policy overriding the SDK defaults, in standard mode unless set
+fn get_retry_config(
+    retries: &RetryOptions,
+    settings: &ProfileSettings,
+) -> Result<Option<RetryConfig>, Error> {
+    let max_attempts = resolve(retries.max_attempts, settings, MAX_ATTEMPTS)?;
+    let mode = resolve(retries.mode, settings, RETRY_MODE)?;
+    if max_attempts.is_none() && mode.is_none() {
+        return Ok(None);
     }
 
-    config.load().await
+    let config = match mode {
+        Some(RetryMode::Adaptive) => RetryConfig::adaptive(),
+        _ => RetryConfig::standard(),
+    };
+
+    Ok(Some(match max_attempts {
+        Some(max_attempts) => config.with_max_attempts(max_attempts),
+        None => config,
+    }))
 }
 
-pub fn get_file_provider(profile: &str, suffix: &str) -> ProfileFileCredentialsProvider {
+fn configure(
+    mut config: ConfigLoader,
+    client: &ClientOptions,
+    settings: &ProfileSettings,
+) -> Result<ConfigLoader, Error> {
+    if let Some(region) = &client.region {
+        config = config.region(Region::new(region.clone()));
+    }
+
+    if let Some(use_fips) = client.use_fips {
+        config = config.use_fips(use_fips);
+    }
+
+    if let Some(use_dual_stack) = client.use_dual_stack {
+        config = config.use_dual_stack(use_dual_stack);
+    }
+
+    if let Some(timeout_config) = get_timeout_config(&client.timeouts, settings)? {
+        config = config.timeout_config(timeout_config);
+    }
+
+    if let Some(retry_config) = get_retry_config(&client.retries, settings)? {
+        config = config.retry_config(retry_config);
+    }
+
+    if let Some(http_client) = &client.http_client {
+        config = config.http_client(http_client.clone());
+    }
+
+    Ok(config)
+}
+
+pub async fn get_profile_config(
+    files: &ProfileFiles,
+    client: &ClientOptions,
+    settings: &ProfileSettings,
+    profile_name: &str,
+) -> Result<SdkConfig, Error> {
+    let config = from_env()
+        .profile_files(get_profile_files(files))
+        .profile_name(profile_name);
+
+    Ok(configure(config, client, settings)?.load().await)
+}
+
+pub async fn get_file_config(
+    files: &ProfileFiles,
+    client: &ClientOptions,
+    settings: &ProfileSettings,
+    profile: &str,
+    suffix: &str,
+) -> Result<SdkConfig, Error> {
+    get_profile_config(files, client, settings, &format!("{profile}-{suffix}")).await
+}
+
+pub fn get_profile_provider(
+    files: &ProfileFiles,
+    profile_name: &str,
+) -> ProfileFileCredentialsProvider {
     ProfileFileCredentialsProvider::builder()
-        .profile_name(format!("{profile}-{suffix}"))
+        .profile_files(get_profile_files(files))
+        .profile_name(profile_name)
         .build()
 }
 
-pub async fn get_env_config() -> SdkConfig {
-    from_env().load().await
+pub fn get_file_provider(
+    files: &ProfileFiles,
+    profile: &str,
+    suffix: &str,
+) -> ProfileFileCredentialsProvider {
+    get_profile_provider(files, &format!("{profile}-{suffix}"))
+}
+
+pub async fn get_env_config(client: &ClientOptions) -> Result<SdkConfig, Error> {
+    Ok(configure(from_env(), client, &ProfileSettings::empty())?
+        .load()
+        .await)
 }
 
 pub fn get_env_provider() -> EnvironmentVariableCredentialsProvider {
     EnvironmentVariableCredentialsProvider::new()
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    use aws_config::retry::RetryMode;
+    use std::time::Duration;
+
+    use crate::config::{
+        get_config_file, get_credentials_file, get_file_config, get_retry_config,
+        get_seed_store_file, get_timeout_config, ProfileSettings, DEFAULT_CONNECT_TIMEOUT,
+    };
+    use crate::ini::Document;
+    use crate::{ClientOptions, ProfileFiles, RetryOptions, TimeoutOptions};
+
+    #[tokio::test]
+    async fn test_get_files_with_explicit_paths() -> Result<()> {
+        assert_eq!(
+            get_config_file("/home/user", Some(String::from("~/project/config"))),
+            "/home/user/project/config"
+        );
+        assert_eq!(
+            get_credentials_file("/home/user", Some(String::from("/tmp/credentials"))),
+            "/tmp/credentials"
+        );
+        assert_eq!(
+            get_seed_store_file("/home/user", Some(String::from("~/seeds.json"))),
+            "/home/user/seeds.json"
+        );
+        assert_eq!(
+            get_credentials_file("/home/user", Some(String::from("~other/credentials"))),
+            "~other/credentials"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_profile_settings() -> Result<()> {
+        let settings = ProfileSettings {
+            file_path: None,
+            document: Document::parse(
+                "
+[profile profile-1-noauth]
+mfa_serial = arn:aws:iam::123456789012:mfa/device_id
+
+[profile profile-1]
+mfa_serial = arn:aws:iam::210987654321:mfa/device_id
+duration_seconds = 43200
+region = eu-west-1
+role_arn = invalid",
+            ),
+            profiles: vec![String::from("profile-1-noauth"), String::from("profile-1")],
+        };
+
+        assert_eq!(
+            settings.get("mfa_serial"),
+            Some(String::from("arn:aws:iam::123456789012:mfa/device_id"))
+        );
+        assert_eq!(settings.parse::<i32>("duration_seconds")?, Some(43200));
+        assert_eq!(settings.parse::<i32>("external_id")?, None);
+        assert!(settings.parse::<i32>("role_arn").is_err());
+        assert!(settings.require("source_profile").is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_file_config_with_endpoint() -> Result<()> {
+        let dir = temp_dir().join(format!("aws-mfa-test-endpoint-{}", std::process::id()));
+        create_dir_all(&dir)?;
+        let config_file = dir.join("config");
+        write(
+            &config_file,
+            "[profile profile-1-noauth]
+region = eu-west-1
+endpoint_url = http://localhost:4566
+use_dualstack_endpoint = true",
+        )?;
+        let files = ProfileFiles::new(
+            &dir.to_string_lossy(),
+            Some(config_file.to_string_lossy().to_string()),
+            Some(dir.join("credentials").to_string_lossy().to_string()),
+            None,
+        );
+
+        let settings = ProfileSettings::empty();
+        let client = ClientOptions::new(
+            None,
+            None,
+            None,
+            None,
+            TimeoutOptions::default(),
+            RetryOptions::default(),
+        );
+        let config = get_file_config(&files, &client, &settings, "profile-1", "noauth").await?;
+        assert_eq!(config.endpoint_url(), Some("http://localhost:4566"));
+        assert_eq!(config.use_dual_stack(), Some(true));
+
+        let client = ClientOptions::new(
+            None,
+            Some(String::from("http://localhost:5000")),
+            Some(true),
+            None,
+            TimeoutOptions::default(),
+            RetryOptions::default(),
+        );
+        let config = get_file_config(&files, &client, &settings, "profile-1", "noauth").await?;
+        assert_eq!(config.endpoint_url(), Some("http://localhost:4566"));
+        assert_eq!(config.use_fips(), Some(true));
+
+        remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_timeout_and_retry_config() -> Result<()> {
+        let settings = ProfileSettings {
+            file_path: None,
+            document: Document::parse(
+                "
+[profile profile-1-noauth]
+read_timeout = 10
+max_attempts = 5
+retry_mode = adaptive",
+            ),
+            profiles: vec![String::from("profile-1-noauth")],
+        };
+
+        assert!(
+            get_timeout_config(&TimeoutOptions::default(), &ProfileSettings::empty())?.is_none()
+        );
+        assert!(get_retry_config(&RetryOptions::default(), &ProfileSettings::empty())?.is_none());
+
+        let timeout_config =
+            get_timeout_config(&TimeoutOptions::new(None, Some(20), Some(60)), &settings)?
+                .expect("timeout config");
+        assert_eq!(
+            timeout_config.connect_timeout(),
+            Some(DEFAULT_CONNECT_TIMEOUT)
+        );
+        assert_eq!(timeout_config.read_timeout(), Some(Duration::from_secs(20)));
+        assert_eq!(
+            timeout_config.operation_timeout(),
+            Some(Duration::from_secs(60))
+        );
+
+        let retry_config =
+            get_retry_config(&RetryOptions::default(), &settings)?.expect("retry config");
+        assert_eq!(retry_config.max_attempts(), 5);
+        assert_eq!(retry_config.mode(), RetryMode::Adaptive);
+
+        let retry_config = get_retry_config(
+            &RetryOptions::new(Some(1), Some(RetryMode::Standard)),
+            &settings,
+        )?
+        .expect("retry config");
+        assert_eq!(retry_config.max_attempts(), 1);
+        assert_eq!(retry_config.mode(), RetryMode::Standard);
+
+        Ok(())
+    }
+}
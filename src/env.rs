@@ -2,6 +2,7 @@ use crate::error::Error;
 use crate::error::Error::{
     GetEnvVariableError, InvalidCredentials, ParseSessionTimestampError, ProvideCredentialsError,
 };
+use crate::totp::{get_totp, Totp};
 use crate::Credentials;
 use aws_config::environment::EnvironmentVariableCredentialsProvider;
 use aws_credential_types::provider::ProvideCredentials;
@@ -9,6 +10,11 @@ use std::env::{var, VarError};
 
 const AWS_SESSION_EXPIRATION_TIMESTAMP: &str = "AWS_SESSION_EXPIRATION_TIMESTAMP";
 
+/// Generator configured with `AWS_MFA_SEED`, `AWS_MFA_ALGORITHM`, `AWS_MFA_DIGITS` and `AWS_MFA_PERIOD`
+pub fn get_env_totp() -> Result<Option<Totp>, Error> {
+    get_totp(|key| var(format!("AWS_{}", key.to_ascii_uppercase())).ok())
+}
+
 pub async fn get_env_credentials(
     provider: EnvironmentVariableCredentialsProvider,
 ) -> Result<Option<Credentials>, Error> {
@@ -1,10 +1,17 @@
 use aws_credential_types::provider::error::CredentialsError;
+use aws_sdk_iam::operation::get_role::GetRoleError;
+use aws_sdk_iam::operation::list_mfa_devices::ListMFADevicesError;
+use aws_sdk_sts::error::ProvideErrorMetadata;
+use aws_sdk_sts::operation::assume_role::AssumeRoleError;
 use aws_sdk_sts::operation::get_caller_identity::GetCallerIdentityError;
+use aws_sdk_sts::operation::get_federation_token::GetFederationTokenError;
 use aws_sdk_sts::operation::get_session_token::GetSessionTokenError;
 use aws_smithy_runtime_api::client::orchestrator::HttpResponse;
-use aws_smithy_runtime_api::client::result::SdkError;
+use aws_smithy_runtime_api::client::result::{DispatchFailure, SdkError};
 use std::env::VarError;
+use std::error::Error as StdError;
 use std::io;
+use std::io::ErrorKind;
 use std::num::ParseIntError;
 use thiserror::Error;
 use time::error::ComponentRange;
@@ -17,24 +24,209 @@ pub enum Error {
     #[error("failed to get session token")]
     GetSessionTokenError(#[source] Box<SdkError<GetSessionTokenError, HttpResponse>>),
 
+    #[error("failed to assume role")]
+    AssumeRoleError(#[source] Box<SdkError<AssumeRoleError, HttpResponse>>),
+
+    #[error("failed to get federation token")]
+    GetFederationTokenError(#[source] Box<SdkError<GetFederationTokenError, HttpResponse>>),
+
+    #[error("failed to list MFA devices")]
+    ListMfaDevicesError(#[source] Box<SdkError<ListMFADevicesError, HttpResponse>>),
+
+    #[error("invalid MFA code, make sure the code is current and was not already used, then retry with the next code")]
+    InvalidMfaCode,
+
+    #[error("invalid MFA code format, expected 6 digits")]
+    InvalidMfaCodeFormat,
+
+    #[error("MFA code was already used with MFA device `{0}`, wait for the next code and retry")]
+    MfaCodeAlreadyUsed(String),
+
+    #[error("MFA device could not be validated, check the device ARN set with --mfa-serial or `mfa_serial` in profile")]
+    MfaDeviceNotFound,
+
+    #[error("access denied: {0}, check the IAM policies attached to the caller")]
+    AccessDenied(String),
+
+    #[error("invalid or expired access key, check or rotate the long-term credentials of the source profile")]
+    InvalidAccessKey,
+
+    #[error("request signature does not match, check the secret access key of the source profile")]
+    InvalidSecretKey,
+
+    #[error("request signature expired, make sure the system clock is synchronized")]
+    ClockSkew,
+
+    #[error(
+        "missing AWS region, use --region or -r, `AWS_REGION` or `region` in profile to provide it"
+    )]
+    MissingRegion,
+
+    #[error("could not reach AWS, check the network connection and any proxy or VPN settings")]
+    NetworkError {
+        sent: bool,
+        source: Box<dyn StdError + Send + Sync>,
+    },
+
+    #[error("request throttled by AWS, retry later or raise --max-attempts")]
+    Throttled,
+
+    #[error("AWS service unavailable ({0}), retry later")]
+    ServiceUnavailable(String),
+
+    #[error("failed to get role")]
+    GetRoleError(#[source] Box<SdkError<GetRoleError, HttpResponse>>),
+
+    #[error("invalid duration `{0}`, use seconds, units like `12h` or `90m`, or `max`")]
+    InvalidDuration(String),
+
+    #[error("session duration of {duration} seconds is out of range, it must be between {min} and {max} seconds for this principal and operation")]
+    DurationOutOfRange { duration: i32, min: i32, max: i32 },
+
+    #[error("no MFA device assigned to caller")]
+    MissingMfaDevice,
+
+    #[error("MFA device `{0}` is a FIDO security key, which cannot be used with an MFA code")]
+    UnsupportedMfaDevice(String),
+
+    #[error("multiple MFA devices found, set `mfa_serial` or `mfa_device_preference` in profile: {}", .0.join(", "))]
+    MultipleMfaDevices(Vec<String>),
+
     #[error("failed to provide credentials")]
     ProvideCredentialsError(#[source] CredentialsError),
 
+    #[error("could not read config file `{path:?}`")]
+    ReadConfigFileError { path: String, source: io::Error },
+
+    #[error("could not write in config file `{path:?}`")]
+    WriteConfigFileError { path: String, source: io::Error },
+
     #[error("could not read credentials file `{path:?}`")]
     ReadCredentialsFileError { path: String, source: io::Error },
 
+    #[error("could not read file `{path:?}`")]
+    ReadSecretFileError { path: String, source: io::Error },
+
+    #[error("could not write in file `{path:?}`")]
+    WriteSecretFileError { path: String, source: io::Error },
+
+    #[error(
+        "invalid seed store `{0:?}`, it is corrupted or was written by an unsupported version"
+    )]
+    InvalidSeedStore(String),
+
+    #[error("missing seed store `{0:?}`, add a seed with `aws-mfa seed add`")]
+    MissingSeedStore(String),
+
+    #[error("wrong passphrase for seed store `{0:?}`")]
+    WrongPassphrase(String),
+
+    #[error("missing passphrase for seed store `{0:?}`, unlock it with `aws-mfa seed unlock` in non-interactive shells")]
+    MissingPassphrase(String),
+
+    #[error("invalid key file `{0:?}`, expected a key cached by `aws-mfa seed unlock`")]
+    InvalidKeyFile(String),
+
+    #[error("passphrases do not match")]
+    PassphraseMismatch,
+
+    #[error("could not derive key from passphrase")]
+    DeriveKeyError,
+
+    #[error("invalid otpauth URI, expected `otpauth://totp/<label>?secret=<mfa_seed>`")]
+    InvalidOtpAuthUri,
+
+    #[error("could not read QR code image `{path:?}`")]
+    ReadQrCodeError {
+        path: String,
+        source: image::ImageError,
+    },
+
+    #[error("no QR code found in image `{0:?}`")]
+    MissingQrCode(String),
+
+    #[error("could not read policy file `{path:?}`")]
+    ReadPolicyFileError { path: String, source: io::Error },
+
+    #[error("policy file `{path:?}` is not a valid JSON document")]
+    InvalidPolicyError {
+        path: String,
+        source: serde_json::Error,
+    },
+
     #[error("could not write in credentials file `{path:?}`")]
     WriteCredentialsFileError { path: String, source: io::Error },
 
+    #[error("credentials file `{path:?}` is readable by other users (mode {mode:o}), restrict it with `chmod 600`")]
+    InsecureCredentialsFileError { path: String, mode: u32 },
+
+    #[error("could not lock file `{path:?}`")]
+    LockFileError { path: String, source: io::Error },
+
     #[error("failed to get environment variable `{var:?}`")]
     GetEnvVariableError { var: String, source: VarError },
 
+    #[error("missing MFA code, use --code, --code-stdin or AWS_MFA_CODE to provide it, set `mfa_code_command` in profile to fetch it, or add the MFA seed with `aws-mfa seed add` to generate it, unlocking the seed store with `aws-mfa seed unlock` in non-interactive shells")]
+    MissingCode,
+
+    #[error("could not run MFA code command `{command}`")]
+    RunCodeCommandError { command: String, source: io::Error },
+
+    #[error("MFA code command `{command}` did not exit within {timeout}s")]
+    CodeCommandTimeout { command: String, timeout: u64 },
+
+    #[error("MFA code command `{command}` failed with {status}")]
+    CodeCommandFailed { command: String, status: String },
+
+    #[error("MFA code command `{0}` did not print a 6-digit MFA code")]
+    InvalidCodeCommandOutput(String),
+
+    #[error("missing MFA seed to generate MFA codes, add it to the seed store with `aws-mfa seed add`, or unlock the seed store with `aws-mfa seed unlock` in non-interactive shells")]
+    MissingMfaSeed,
+
+    #[error("invalid MFA seed, expected a Base32 encoded secret")]
+    InvalidMfaSeed,
+
+    #[error("invalid value `{value}` for MFA setting `{key}`")]
+    InvalidMfaSetting { key: String, value: String },
+
+    #[error("missing setting `{key}` in profile `{profile}` of config file")]
+    MissingProfileSetting { profile: String, key: String },
+
+    #[error("invalid value `{value}` for setting `{key}` in profile `{profile}` of config file")]
+    InvalidProfileSetting {
+        profile: String,
+        key: String,
+        value: String,
+    },
+
+    #[error("invalid env format `{0}`, expected bash, zsh, fish, powershell, nushell, cmd, dotenv, json or github-actions")]
+    InvalidEnvFormat(String),
+
+    #[error("value of `{0}` cannot be set in cmd, which has no escape for `\"`, `%` or line breaks, use another format like powershell")]
+    UnsupportedCmdValue(String),
+
+    #[error("missing GITHUB_ENV environment variable, the github-actions format only works in GitHub Actions")]
+    MissingGithubEnv,
+
+    #[error("could not write in GitHub Actions env file `{path:?}`")]
+    WriteGithubEnvError { path: String, source: io::Error },
+
     #[error("missing field `{0}` in session token")]
     InvalidSession(String),
 
     #[error("missing field `{0}` in session credentials")]
     InvalidCredentials(String),
 
+    #[error("invalid arn `{0}`")]
+    InvalidArn(String),
+
+    #[error("caller `{0}` is not an IAM user or root account, GetSessionToken requires long-term access keys of an IAM user")]
+    UnsupportedPrincipal(String),
+
+    #[error("missing field `{0}` in role")]
+    InvalidRole(String),
+
     #[error("missing field `{0}` in caller identity")]
     InvalidIdentity(String),
 
@@ -47,3 +239,140 @@ pub enum Error {
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
+
+impl Error {
+    /// Classify a failed AWS call into an actionable variant, falling back to `other` for unknown failures
+    pub(crate) fn from_sdk<E>(
+        error: SdkError<E, HttpResponse>,
+        other: impl FnOnce(Box<SdkError<E, HttpResponse>>) -> Self,
+    ) -> Self
+    where
+        E: ProvideErrorMetadata + StdError + Send + Sync + 'static,
+    {
+        match &error {
+            SdkError::DispatchFailure(failure) => {
+                return Self::NetworkError {
+                    sent: !failed_to_connect(failure),
+                    source: Box::new(error),
+                }
+            }
+            SdkError::TimeoutError(_) => {
+                return Self::NetworkError {
+                    sent: true,
+                    source: Box::new(error),
+                }
+            }
+            SdkError::ServiceError(e) if e.raw().status().is_server_error() => {
+                return Self::ServiceUnavailable(error.code().unwrap_or("unknown").to_string())
+            }
+            _ => {}
+        }
+
+        let message = error.message().unwrap_or_default();
+        match error.code().unwrap_or_default() {
+            "AccessDenied" if message.contains("invalid MFA one time pass code") => {
+                Self::InvalidMfaCode
+            }
+            "AccessDenied" if message.contains("MFA serial number") => Self::MfaDeviceNotFound,
+            "AccessDenied" => Self::AccessDenied(String::from(message)),
+            "InvalidClientTokenId" | "ExpiredToken" => Self::InvalidAccessKey,
+            "SignatureDoesNotMatch" if message.contains("Signature expired") => Self::ClockSkew,
+            "SignatureDoesNotMatch" => Self::InvalidSecretKey,
+            "RequestExpired" | "RequestTimeTooSkewed" => Self::ClockSkew,
+            "Throttling" | "ThrottlingException" | "RequestLimitExceeded" => Self::Throttled,
+            _ => other(Box::new(error)),
+        }
+    }
+
+    /// Whether the failure is transient, so the call may succeed when retried
+    pub(crate) fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::NetworkError { .. } | Self::Throttled | Self::ServiceUnavailable(_)
+        )
+    }
+
+    /// Whether the request was certainly not processed by AWS, so an MFA code it carried was not consumed
+    pub(crate) fn is_unprocessed(&self) -> bool {
+        matches!(
+            self,
+            Self::NetworkError { sent: false, .. } | Self::Throttled
+        )
+    }
+}
+
+/// Whether the request failed while connecting, before anything was sent to AWS
+///
+/// Only IO failures of the connector that never connected and were caused by an unreachable endpoint qualify,
+/// any other failure may have reached AWS.
+fn failed_to_connect(failure: &DispatchFailure) -> bool {
+    let Some(error) = failure.as_connector_error() else {
+        return false;
+    };
+    if !error.is_io() || error.connection_metadata().is_some() {
+        return false;
+    }
+
+    let mut source = error.source();
+    while let Some(error) = source {
+        if let Some(e) = error.downcast_ref::<io::Error>() {
+            if matches!(
+                e.kind(),
+                ErrorKind::ConnectionRefused
+                    | ErrorKind::HostUnreachable
+                    | ErrorKind::NetworkUnreachable
+                    | ErrorKind::AddrNotAvailable
+            ) {
+                return true;
+            }
+        }
+
+        source = error.source();
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use aws_smithy_runtime_api::client::result::{ConnectorError, SdkError};
+    use std::io;
+    use std::io::ErrorKind;
+
+    use crate::error::Error;
+    use crate::error::Error::{GetSessionTokenError, NetworkError};
+
+    fn network_error(error: ConnectorError) -> Error {
+        Error::from_sdk(SdkError::dispatch_failure(error), GetSessionTokenError)
+    }
+
+    #[tokio::test]
+    async fn test_network_error_sent() -> Result<()> {
+        let refused = io::Error::from(ErrorKind::ConnectionRefused);
+        assert!(matches!(
+            network_error(ConnectorError::io(Box::new(refused))),
+            NetworkError { sent: false, .. }
+        ));
+
+        let reset = io::Error::from(ErrorKind::ConnectionReset);
+        assert!(matches!(
+            network_error(ConnectorError::io(Box::new(reset))),
+            NetworkError { sent: true, .. }
+        ));
+
+        let refused = io::Error::from(ErrorKind::ConnectionRefused);
+        assert!(matches!(
+            network_error(ConnectorError::timeout(Box::new(refused))),
+            NetworkError { sent: true, .. }
+        ));
+
+        let refused = io::Error::from(ErrorKind::ConnectionRefused);
+        assert!(matches!(
+            network_error(ConnectorError::other(Box::new(refused), None)),
+            NetworkError { sent: true, .. }
+        ));
+
+        Ok(())
+    }
+}
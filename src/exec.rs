@@ -0,0 +1,173 @@
+use std::process::ExitStatus;
+
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::process::{Child, Command};
+
+use crate::error::Error;
+use crate::error::Error::{ConvertSessionTimestampError, Other};
+use crate::Credentials;
+
+/// Command run with `credentials` and `region` in its environment, without the profile variables that would take precedence over them
+pub fn exec_command(
+    command: &[String],
+    credentials: &Credentials,
+    region: Option<&str>,
+) -> Result<Command, Error> {
+    let expiration =
+        OffsetDateTime::from_unix_timestamp(credentials.session_expiration_timestamp())
+            .map_err(ConvertSessionTimestampError)?
+            .format(&Rfc3339)
+            .map_err(|e| Other(e.into()))?;
+
+    let mut child = Command::new(&command[0]);
+    child
+        .args(&command[1..])
+        .env_remove("AWS_PROFILE")
+        .env_remove("AWS_DEFAULT_PROFILE")
+        .env("AWS_ACCESS_KEY_ID", credentials.access_key_id())
+        .env("AWS_SECRET_ACCESS_KEY", credentials.secret_access_key())
+        .env("AWS_SESSION_TOKEN", credentials.session_token())
+        .env(
+            "AWS_SESSION_EXPIRATION_TIMESTAMP",
+            credentials.session_expiration_timestamp().to_string(),
+        )
+        .env("AWS_CREDENTIAL_EXPIRATION", expiration);
+    if let Some(region) = region {
+        child
+            .env("AWS_REGION", region)
+            .env("AWS_DEFAULT_REGION", region);
+    }
+
+    Ok(child)
+}
+
+/// Wait for the child process, forwarding interrupt, quit, termination and hangup signals to it
+///
+/// The child stays in the foreground process group so that it can read from the terminal,
+/// so it may receive a signal sent by the terminal both directly and forwarded.
+#[cfg(unix)]
+pub async fn wait_child(child: &mut Child) -> std::io::Result<ExitStatus> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut quit = signal(SignalKind::quit())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    loop {
+        let number = tokio::select! {
+            status = child.wait() => return status,
+            _ = interrupt.recv() => libc::SIGINT,
+            _ = quit.recv() => libc::SIGQUIT,
+            _ = terminate.recv() => libc::SIGTERM,
+            _ = hangup.recv() => libc::SIGHUP,
+        };
+
+        if let Some(pid) = child.id() {
+            // SAFETY: kill only sends a signal to the child process and has no memory effects
+            unsafe { libc::kill(pid as libc::pid_t, number) };
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn wait_child(child: &mut Child) -> std::io::Result<ExitStatus> {
+    child.wait().await
+}
+
+/// Exit code of the child process, or 128 plus the number of the signal that terminated it
+pub fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return 128 + signal;
+    }
+
+    status.code().unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use std::ffi::OsStr;
+    use std::time::Duration;
+    use tokio::time::sleep;
+
+    use crate::exec::{exec_command, exit_code, wait_child};
+    use crate::Credentials;
+
+    fn credentials() -> Credentials {
+        Credentials::new("access_key_id", "secret_access_key", "session_token", 0)
+    }
+
+    #[tokio::test]
+    async fn test_exec_command() -> Result<()> {
+        let command = exec_command(
+            &[String::from("aws"), String::from("s3"), String::from("ls")],
+            &credentials(),
+            Some("eu-west-1"),
+        )?;
+        let command = command.as_std();
+        let envs = command.get_envs().collect::<Vec<_>>();
+
+        assert_eq!(command.get_program(), "aws");
+        assert_eq!(command.get_args().collect::<Vec<_>>(), vec!["s3", "ls"]);
+        for name in ["AWS_PROFILE", "AWS_DEFAULT_PROFILE"] {
+            assert!(envs.contains(&(OsStr::new(name), None)));
+        }
+        for (name, value) in [
+            ("AWS_ACCESS_KEY_ID", "access_key_id"),
+            ("AWS_SECRET_ACCESS_KEY", "secret_access_key"),
+            ("AWS_SESSION_TOKEN", "session_token"),
+            ("AWS_SESSION_EXPIRATION_TIMESTAMP", "0"),
+            ("AWS_CREDENTIAL_EXPIRATION", "1970-01-01T00:00:00Z"),
+            ("AWS_REGION", "eu-west-1"),
+            ("AWS_DEFAULT_REGION", "eu-west-1"),
+        ] {
+            assert!(envs.contains(&(OsStr::new(name), Some(OsStr::new(value)))));
+        }
+
+        let command = exec_command(&[String::from("aws")], &credentials(), None)?;
+        assert!(!command
+            .as_std()
+            .get_envs()
+            .any(|(name, _)| name == "AWS_REGION"));
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_exit_code() -> Result<()> {
+        let exit = |script: &str| {
+            let command = [String::from("sh"), String::from("-c"), String::from(script)];
+            async move {
+                let mut child = exec_command(&command, &credentials(), None)?.spawn()?;
+                Ok::<_, anyhow::Error>(exit_code(wait_child(&mut child).await?))
+            }
+        };
+
+        assert_eq!(exit("exit 0").await?, 0);
+        assert_eq!(exit("exit 7").await?, 7);
+        assert_eq!(exit("kill -TERM $$").await?, 143);
+        assert_eq!(
+            exit("[ -z \"$AWS_PROFILE\" ] && [ \"$AWS_SESSION_TOKEN\" = session_token ]").await?,
+            0
+        );
+
+        let command = [
+            String::from("sh"),
+            String::from("-c"),
+            String::from("trap 'exit 5' INT; while true; do sleep 0.1; done"),
+        ];
+        let mut child = exec_command(&command, &credentials(), None)?.spawn()?;
+        let (status, _) = tokio::join!(wait_child(&mut child), async {
+            sleep(Duration::from_millis(500)).await;
+            // SAFETY: kill only sends a signal to this process, whose handler is registered by wait_child
+            unsafe { libc::kill(libc::getpid(), libc::SIGINT) };
+        });
+        assert_eq!(exit_code(status?), 5);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::Result;
+use aws_config::SdkConfig;
+use aws_credential_types::provider::ProvideCredentials;
+use aws_sdk_iam::config::Builder;
+use aws_sdk_iam::Client;
+
+use crate::arn::Arn;
+use crate::error::Error;
+use crate::error::Error::{GetRoleError, InvalidRole, ListMfaDevicesError};
+
+pub fn get_iam_client(config: &SdkConfig, provider: impl ProvideCredentials + 'static) -> Client {
+    let builder = Builder::from(config).credentials_provider(provider);
+
+    Client::from_conf(builder.build())
+}
+
+/// List serial numbers of MFA devices assigned to the calling IAM user, from all pages
+pub async fn list_mfa_devices(client: &Client) -> Result<Vec<String>, Error> {
+    let devices = client
+        .list_mfa_devices()
+        .into_paginator()
+        .items()
+        .send()
+        .try_collect()
+        .await
+        .map_err(|e| Error::from_sdk(e, ListMfaDevicesError))?;
+
+    Ok(devices
+        .iter()
+        .map(|d| String::from(d.serial_number()))
+        .collect())
+}
+
+/// Maximum session duration of a role, which requires `iam:GetRole` in the account of the role
+pub async fn get_max_session_duration(client: &Client, role_arn: &str) -> Result<i32, Error> {
+    let arn = role_arn.parse::<Arn>()?;
+    let name = arn.resource().rsplit('/').next().unwrap_or_default();
+    let role = client
+        .get_role()
+        .role_name(name)
+        .send()
+        .await
+        .map_err(|e| Error::from_sdk(e, GetRoleError))?;
+
+    role.role()
+        .and_then(|r| r.max_session_duration())
+        .ok_or_else(|| InvalidRole(String::from("max_session_duration")))
+}
+
+/// Whether the device is a FIDO security key, which cannot be used with a token code
+pub fn is_fido_device(serial: &str) -> bool {
+    serial.contains(":u2f/")
+}
+
+/// Select the first device matching a preference, either by serial or by device name
+pub fn find_preferred_device(devices: &[String], preferences: &str) -> Option<String> {
+    preferences
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .find_map(|p| {
+            devices
+                .iter()
+                .find(|d| d.as_str() == p || d.ends_with(&format!("/{p}")))
+                .cloned()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use aws_credential_types::Credentials;
+    use aws_sdk_iam::config::Region;
+    use aws_sdk_iam::{Client, Config};
+    use aws_smithy_http_client::test_util::{ReplayEvent, StaticReplayClient};
+    use aws_smithy_types::body::SdkBody;
+    use http::{Method, Request, Response};
+
+    use crate::iam::{find_preferred_device, is_fido_device, list_mfa_devices};
+
+    #[tokio::test]
+    async fn test_list_mfa_devices() -> Result<()> {
+        let credentials = Credentials::new("", "", None, None, "");
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://iam.amazonaws.com/")
+            .body(SdkBody::from("Action=ListMFADevices&Version=2010-05-08"))?;
+        let response = Response::builder().status(200).body(SdkBody::from(
+            "
+        <ListMFADevicesResponse>
+            <ListMFADevicesResult>
+                <IsTruncated>true</IsTruncated>
+                <Marker>marker</Marker>
+                <MFADevices>
+                    <member>
+                        <UserName>user_name</UserName>
+                        <SerialNumber>arn:aws:iam::account:mfa/phone</SerialNumber>
+                        <EnableDate>2022-08-31T19:55:58Z</EnableDate>
+                    </member>
+                </MFADevices>
+            </ListMFADevicesResult>
+        </ListMFADevicesResponse>",
+        ))?;
+        let next_request = Request::builder()
+            .method(Method::POST)
+            .uri("https://iam.amazonaws.com/")
+            .body(SdkBody::from(
+                "Action=ListMFADevices&Version=2010-05-08&Marker=marker",
+            ))?;
+        let next_response = Response::builder().status(200).body(SdkBody::from(
+            "
+        <ListMFADevicesResponse>
+            <ListMFADevicesResult>
+                <IsTruncated>false</IsTruncated>
+                <MFADevices>
+                    <member>
+                        <UserName>user_name</UserName>
+                        <SerialNumber>arn:aws:iam::account:u2f/user/user_name/key-ABCDEF</SerialNumber>
+                        <EnableDate>2022-08-31T19:55:58Z</EnableDate>
+                    </member>
+                </MFADevices>
+            </ListMFADevicesResult>
+        </ListMFADevicesResponse>",
+        ))?;
+        let replay_client = StaticReplayClient::new(vec![
+            ReplayEvent::new(request, response),
+            ReplayEvent::new(next_request, next_response),
+        ]);
+        let conf = Config::builder()
+            .behavior_version_latest()
+            .region(Region::new("us-east-1"))
+            .credentials_provider(credentials)
+            .http_client(replay_client.clone())
+            .build();
+        let client = Client::from_conf(conf);
+        let devices = list_mfa_devices(&client).await?;
+
+        replay_client.assert_requests_match(&[]);
+        assert_eq!(
+            devices,
+            vec![
+                "arn:aws:iam::account:mfa/phone",
+                "arn:aws:iam::account:u2f/user/user_name/key-ABCDEF"
+            ]
+        );
+        assert!(!is_fido_device(&devices[0]));
+        assert!(is_fido_device(&devices[1]));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_preferred_device() -> Result<()> {
+        let devices = vec![
+            String::from("arn:aws:iam::account:mfa/phone"),
+            String::from("arn:aws:iam::account:mfa/laptop"),
+        ];
+
+        assert_eq!(
+            find_preferred_device(&devices, "tablet, laptop, phone"),
+            Some(String::from("arn:aws:iam::account:mfa/laptop"))
+        );
+        assert_eq!(
+            find_preferred_device(&devices, "arn:aws:iam::account:mfa/phone"),
+            Some(String::from("arn:aws:iam::account:mfa/phone"))
+        );
+        assert_eq!(find_preferred_device(&devices, "tablet"), None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,380 @@
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+enum Kind {
+    Section(String),
+    Property(String, String),
+    Other,
+}
+
+struct Line {
+    raw: String,
+    kind: Kind,
+}
+
+impl Line {
+    fn new(raw: &str) -> Self {
+        let content = raw.trim_end_matches(['\r', '\n']);
+        let trimmed = content.trim();
+
+        let kind = if content.starts_with(char::is_whitespace)
+            || trimmed.starts_with('#')
+            || trimmed.starts_with(';')
+        {
+            Kind::Other
+        } else if let Some(section) = trimmed
+            .strip_prefix('[')
+            .and_then(|s| s.split_once(']'))
+            .map(|(s, _)| s.trim())
+        {
+            Kind::Section(String::from(section))
+        } else if let Some((key, value)) = trimmed.split_once('=') {
+            Kind::Property(String::from(key.trim()), String::from(value.trim()))
+        } else {
+            Kind::Other
+        };
+
+        Self {
+            raw: String::from(raw),
+            kind,
+        }
+    }
+
+    fn ending(&self) -> &str {
+        let content = self.raw.trim_end_matches(['\r', '\n']);
+        &self.raw[content.len()..]
+    }
+
+    fn is_blank(&self) -> bool {
+        self.raw.trim().is_empty()
+    }
+
+    fn is_comment(&self) -> bool {
+        let trimmed = self.raw.trim();
+        trimmed.starts_with('#') || trimmed.starts_with(';')
+    }
+}
+
+/// INI document keeping every line verbatim so that untouched content round-trips byte for byte
+pub struct Document {
+    lines: Vec<Line>,
+    newline: &'static str,
+}
+
+impl Document {
+    pub fn parse(content: &str) -> Self {
+        let newline = if content.contains("\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        };
+        let lines = content.split_inclusive('\n').map(Line::new).collect();
+
+        Self { lines, newline }
+    }
+
+    /// Names of all sections in order of appearance
+    pub fn sections(&self) -> Vec<&str> {
+        let mut sections = Vec::new();
+        for line in &self.lines {
+            if let Kind::Section(name) = &line.kind {
+                if !sections.contains(&name.as_str()) {
+                    sections.push(name.as_str());
+                }
+            }
+        }
+
+        sections
+    }
+
+    pub fn keys(&self, section: &str) -> Vec<&str> {
+        let Some(range) = self.section_range(section) else {
+            return Vec::new();
+        };
+
+        self.lines[range]
+            .iter()
+            .filter_map(|l| match &l.kind {
+                Kind::Property(k, _) => Some(k.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        let range = self.section_range(section)?;
+
+        self.lines[range].iter().find_map(|l| match &l.kind {
+            Kind::Property(k, v) if k == key => Some(v.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Set the value of `key` in `section`, appending the key or the section when missing
+    pub fn set(&mut self, section: &str, key: &str, value: &str) {
+        let range = match self.section_range(section) {
+            Some(range) => range,
+            None => self.push_section(section),
+        };
+
+        let existing = self.lines[range.clone()]
+            .iter()
+            .position(|l| matches!(&l.kind, Kind::Property(k, _) if k == key));
+
+        match existing {
+            Some(index) => {
+                let line = &mut self.lines[range.start + index];
+                line.raw = format!("{key} = {value}{}", line.ending());
+                line.kind = Kind::Property(String::from(key), String::from(value));
+            }
+            None => {
+                let index = self.lines[range.clone()]
+                    .iter()
+                    .rposition(|l| matches!(l.kind, Kind::Property(..) | Kind::Section(_)))
+                    .map_or(range.start, |i| range.start + i);
+                self.terminate(index);
+                let line = Line::new(&format!("{key} = {value}{}", self.newline));
+                self.lines.insert(index + 1, line);
+            }
+        }
+    }
+
+    /// Remove `key` from `section`, returning whether it existed
+    pub fn remove(&mut self, section: &str, key: &str) -> bool {
+        let Some(range) = self.section_range(section) else {
+            return false;
+        };
+
+        match self.lines[range.clone()]
+            .iter()
+            .position(|l| matches!(&l.kind, Kind::Property(k, _) if k == key))
+        {
+            Some(index) => {
+                self.lines.remove(range.start + index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove `section` with all its lines, returning whether it existed
+    ///
+    /// Comments at the end of the section are kept, since they usually describe the next section.
+    pub fn remove_section(&mut self, section: &str) -> bool {
+        match self.section_range(section) {
+            Some(range) => {
+                let trailing = self.lines[range.clone()]
+                    .iter()
+                    .rposition(|l| !l.is_blank() && !l.is_comment())
+                    .map_or(range.start + 1, |i| range.start + i + 1);
+                let end = self.lines[trailing..range.end]
+                    .iter()
+                    .position(Line::is_comment)
+                    .map_or(range.end, |i| trailing + i);
+                self.lines.drain(range.start..end);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn section_range(&self, section: &str) -> Option<Range<usize>> {
+        let start = self
+            .lines
+            .iter()
+            .position(|l| matches!(&l.kind, Kind::Section(s) if s == section))?;
+        let end = self.lines[start + 1..]
+            .iter()
+            .position(|l| matches!(l.kind, Kind::Section(_)))
+            .map_or(self.lines.len(), |i| start + 1 + i);
+
+        Some(start..end)
+    }
+
+    fn push_section(&mut self, section: &str) -> Range<usize> {
+        if let Some(last) = self.lines.len().checked_sub(1) {
+            self.terminate(last);
+            if !self.lines[last].is_blank() {
+                self.lines.push(Line::new(self.newline));
+            }
+        }
+
+        let start = self.lines.len();
+        self.lines
+            .push(Line::new(&format!("[{section}]{}", self.newline)));
+
+        start..self.lines.len()
+    }
+
+    fn terminate(&mut self, index: usize) {
+        let line = &mut self.lines[index];
+        if line.ending().is_empty() {
+            line.raw.push_str(self.newline);
+        }
+    }
+}
+
+impl Display for Document {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for line in &self.lines {
+            f.write_str(&line.raw)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::ini::Document;
+
+    #[tokio::test]
+    async fn test_parse_round_trip() -> Result<()> {
+        let content = "# comment\r
+[profile-1]\r
+aws_access_key_id=aws_access_key_id_1\r
+; another comment\r
+\r
+\r
+\r
+[profile-2]  \r
+  nested = value\r
+aws_access_key_id   =   aws_access_key_id_2";
+
+        let document = Document::parse(content);
+        assert_eq!(document.to_string(), content);
+        assert_eq!(document.sections(), vec!["profile-1", "profile-2"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get() -> Result<()> {
+        let content = "
+[profile-1]
+# aws_access_key_id = commented_out
+aws_access_key_id=aws_access_key_id_1
+
+
+[profile-2]
+aws_access_key_id = aws_access_key_id_2
+aws_secret_access_key = aws_secret_access_key_2";
+
+        let document = Document::parse(content);
+        assert_eq!(
+            document.get("profile-1", "aws_access_key_id"),
+            Some("aws_access_key_id_1")
+        );
+        assert_eq!(document.get("profile-1", "aws_secret_access_key"), None);
+        assert_eq!(
+            document.get("profile-2", "aws_secret_access_key"),
+            Some("aws_secret_access_key_2")
+        );
+        assert_eq!(document.get("profile-3", "aws_access_key_id"), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_set() -> Result<()> {
+        let content = "[profile-1]\r
+aws_access_key_id=aws_access_key_id_1\r
+# comment\r
+\r
+[profile-2]\r
+region = eu-west-1";
+
+        let mut document = Document::parse(content);
+        document.set("profile-1", "aws_access_key_id", "aws_access_key_id_3");
+        document.set("profile-1", "aws_session_token", "aws_session_token_3");
+        document.set("profile-2", "aws_access_key_id", "aws_access_key_id_4");
+        document.set("profile-3", "aws_access_key_id", "aws_access_key_id_5");
+
+        let expected_content = "[profile-1]\r
+aws_access_key_id = aws_access_key_id_3\r
+aws_session_token = aws_session_token_3\r
+# comment\r
+\r
+[profile-2]\r
+region = eu-west-1\r
+aws_access_key_id = aws_access_key_id_4\r
+\r
+[profile-3]\r
+aws_access_key_id = aws_access_key_id_5\r
+";
+        assert_eq!(document.to_string(), expected_content);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remove() -> Result<()> {
+        let content = "[profile-1]\r
+aws_access_key_id = aws_access_key_id_1\r
+mfa_seed = mfa_seed_1\r
+# comment\r
+\r
+[profile-2]\r
+mfa_seed = mfa_seed_2";
+
+        let mut document = Document::parse(content);
+        assert!(document.remove("profile-1", "mfa_seed"));
+        assert!(document.remove("profile-2", "mfa_seed"));
+        assert!(!document.remove("profile-2", "mfa_seed"));
+        assert!(!document.remove("profile-3", "mfa_seed"));
+        assert_eq!(document.keys("profile-1"), vec!["aws_access_key_id"]);
+        assert!(document.keys("profile-2").is_empty());
+
+        let expected_content = "[profile-1]\r
+aws_access_key_id = aws_access_key_id_1\r
+# comment\r
+\r
+[profile-2]\r
+";
+        assert_eq!(document.to_string(), expected_content);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remove_section() -> Result<()> {
+        let content = "[profile-1]
+aws_access_key_id = aws_access_key_id_1
+
+# comment
+[profile-2]
+aws_access_key_id = aws_access_key_id_2
+
+# profile-3 comment
+; another comment
+[profile-3]
+aws_access_key_id = aws_access_key_id_3
+
+[profile-4]
+aws_access_key_id = aws_access_key_id_4
+# trailing comment
+";
+
+        let mut document = Document::parse(content);
+        assert!(document.remove_section("profile-2"));
+        assert!(document.remove_section("profile-4"));
+        assert!(!document.remove_section("profile-5"));
+
+        let expected_content = "[profile-1]
+aws_access_key_id = aws_access_key_id_1
+
+# comment
+# profile-3 comment
+; another comment
+[profile-3]
+aws_access_key_id = aws_access_key_id_3
+
+# trailing comment
+";
+        assert_eq!(document.to_string(), expected_content);
+
+        Ok(())
+    }
+}
@@ -1,170 +1,626 @@
-use std::fs::{read_to_string, write};
-use std::ops::Add;
+use std::fs::{
+    canonicalize, create_dir_all, metadata, read_to_string, remove_file, rename, set_permissions,
+    DirBuilder, File, OpenOptions,
+};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::process;
 
 use anyhow::Result;
 
-use crate::config::get_credentials_file;
+use crate::config::{MFA_ALGORITHM, MFA_DIGITS, MFA_PERIOD, MFA_SEED};
 use crate::error::Error;
-use crate::error::Error::{ReadCredentialsFileError, WriteCredentialsFileError};
-use crate::Credentials;
+use crate::error::Error::{
+    InsecureCredentialsFileError, InvalidPolicyError, LockFileError, ReadConfigFileError,
+    ReadCredentialsFileError, ReadPolicyFileError, ReadSecretFileError, WriteConfigFileError,
+    WriteCredentialsFileError, WriteSecretFileError,
+};
+use crate::ini::Document;
+use crate::store::SeedEntry;
+use crate::{Credentials, Session};
 
 const AWS_ACCESS_KEY_ID: &str = "aws_access_key_id";
 const AWS_SECRET_ACCESS_KEY: &str = "aws_secret_access_key";
 const AWS_SESSION_TOKEN: &str = "aws_session_token";
 const AWS_SESSION_EXPIRATION_TIMESTAMP: &str = "aws_session_expiration_timestamp";
 
-fn find_credential_value(credentials: &str, key: &str) -> Option<String> {
-    let pattern = format!("{} = ", key);
-    credentials
-        .split('\n')
-        .find(|l| l.contains(&pattern))
-        .map(|c| c.replace(&pattern, ""))
-}
-
-fn find_credentials(file_content: &str, profile: &str) -> Option<Credentials> {
-    let credentials = file_content
-        .split("\n\n")
-        .find(|l| l.contains(format!("[{profile}]").as_str()))?;
-
-    let access_key_id = find_credential_value(credentials, AWS_ACCESS_KEY_ID)?;
-    let secret_access_key = find_credential_value(credentials, AWS_SECRET_ACCESS_KEY)?;
-    let session_token = find_credential_value(credentials, AWS_SESSION_TOKEN)?;
-
-    let session_expiration_timestamp =
-        match find_credential_value(credentials, AWS_SESSION_EXPIRATION_TIMESTAMP) {
-            Some(e) => match e.parse::<i64>() {
-                Ok(e) => e,
-                Err(_) => return None,
-            },
-            None => return None,
-        };
+fn find_credentials(document: &Document, profile: &str) -> Option<Credentials> {
+    let access_key_id = document.get(profile, AWS_ACCESS_KEY_ID)?;
+    let secret_access_key = document.get(profile, AWS_SECRET_ACCESS_KEY)?;
+    let session_token = document.get(profile, AWS_SESSION_TOKEN)?;
+    let session_expiration_timestamp = document
+        .get(profile, AWS_SESSION_EXPIRATION_TIMESTAMP)?
+        .parse::<i64>()
+        .ok()?;
 
     Some(Credentials::new(
-        &access_key_id,
-        &secret_access_key,
-        &session_token,
+        access_key_id,
+        secret_access_key,
+        session_token,
         session_expiration_timestamp,
     ))
 }
 
-fn replace_credentials(file_content: &str, profile: &str, content: &str) -> String {
-    file_content
-        .split("\n\n")
-        .filter(|l| !l.is_empty() && !l.contains(format!("[{profile}]").as_str()))
-        .collect::<Vec<&str>>()
-        .join("\n\n")
-        .add(content)
+fn find_sessions(document: &Document, suffix: &str) -> Vec<Session> {
+    let sections = document.sections();
+    let source_suffix = format!("-{suffix}");
+
+    sections
+        .iter()
+        .filter(|s| !s.ends_with(&source_suffix))
+        .filter(|s| document.get(s, AWS_SESSION_EXPIRATION_TIMESTAMP).is_some())
+        .filter_map(|s| {
+            let credentials = find_credentials(document, s)?;
+            let source_profile = format!("{s}-{suffix}");
+            let source_profile = sections
+                .contains(&source_profile.as_str())
+                .then_some(source_profile);
+
+            Some(Session::new(s, source_profile, credentials))
+        })
+        .collect()
 }
 
-pub fn find_auth_credentials(home: &str, profile: &str) -> Result<Option<Credentials>, Error> {
-    let file_path = get_credentials_file(home);
-    let file_content = read_to_string(file_path.clone()).map_err(|e| ReadCredentialsFileError {
-        path: file_path,
-        source: e,
-    })?;
-    let credentials = find_credentials(&file_content, profile);
+/// Remove the credentials of sessions, and their profiles when no other settings are left
+fn remove_sessions(document: &mut Document, suffix: &str, all: bool) -> Vec<Session> {
+    let sessions = find_sessions(document, suffix)
+        .into_iter()
+        .filter(|s| all || s.credentials().expired())
+        .collect::<Vec<Session>>();
+
+    for session in &sessions {
+        let profile = session.profile();
+        for key in [
+            AWS_ACCESS_KEY_ID,
+            AWS_SECRET_ACCESS_KEY,
+            AWS_SESSION_TOKEN,
+            AWS_SESSION_EXPIRATION_TIMESTAMP,
+        ] {
+            document.remove(profile, key);
+        }
+        if document.keys(profile).is_empty() {
+            document.remove_section(profile);
+        }
+    }
 
-    Ok(credentials)
+    sessions
 }
 
-pub fn save_auth_credentials(
-    home: &str,
-    profile: &str,
-    credentials: &Credentials,
-) -> Result<(), Error> {
-    let content = format!(
-        "
-
-[{profile}]
-{} = {}
-{} = {}
-{} = {}
-{} = {}",
-        AWS_ACCESS_KEY_ID,
-        credentials.access_key_id(),
+/// Entries of profiles with `mfa_seed` in plaintext, with their TOTP settings
+fn find_seeds(document: &Document) -> Vec<SeedEntry> {
+    document
+        .sections()
+        .into_iter()
+        .filter(|s| document.get(s, MFA_SEED).is_some())
+        .map(|s| SeedEntry::from_settings(s, |key| document.get(s, key).map(String::from)))
+        .collect()
+}
+
+fn remove_seeds(document: &mut Document, profiles: &[String]) {
+    for profile in profiles {
+        for key in [MFA_SEED, MFA_ALGORITHM, MFA_DIGITS, MFA_PERIOD] {
+            document.remove(profile, key);
+        }
+    }
+}
+
+fn replace_credentials(document: &mut Document, profile: &str, credentials: &Credentials) {
+    document.set(profile, AWS_ACCESS_KEY_ID, credentials.access_key_id());
+    document.set(
+        profile,
         AWS_SECRET_ACCESS_KEY,
         credentials.secret_access_key(),
-        AWS_SESSION_TOKEN,
-        credentials.session_token(),
+    );
+    document.set(profile, AWS_SESSION_TOKEN, credentials.session_token());
+    document.set(
+        profile,
         AWS_SESSION_EXPIRATION_TIMESTAMP,
-        credentials.session_expiration_timestamp(),
+        &credentials.session_expiration_timestamp().to_string(),
     );
+}
 
-    let file_path = get_credentials_file(home);
-    let file_content = read_to_string(&file_path).map_err(|e| ReadCredentialsFileError {
-        path: file_path.clone(),
+fn read_credentials_file(file_path: &str) -> Result<Document, Error> {
+    let file_content = read_to_string(file_path).map_err(|e| ReadCredentialsFileError {
+        path: String::from(file_path),
         source: e,
     })?;
-    let new_content = replace_credentials(&file_content, profile, &content);
 
-    write(file_path.clone(), new_content.as_bytes()).map_err(|e| WriteCredentialsFileError {
-        path: file_path,
+    Ok(Document::parse(&file_content))
+}
+
+/// Read the config file, which is optional and treated as empty when missing
+pub fn read_config_file(file_path: &str) -> Result<Document, Error> {
+    match read_to_string(file_path) {
+        Ok(file_content) => Ok(Document::parse(&file_content)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Document::parse("")),
+        Err(e) => Err(ReadConfigFileError {
+            path: String::from(file_path),
+            source: e,
+        }),
+    }
+}
+
+/// Read a session policy file, checking that it contains a JSON document
+pub fn read_policy_file(file_path: &str) -> Result<String, Error> {
+    let policy = read_to_string(file_path).map_err(|e| ReadPolicyFileError {
+        path: String::from(file_path),
         source: e,
     })?;
 
+    serde_json::from_str::<serde_json::Value>(&policy).map_err(|e| InvalidPolicyError {
+        path: String::from(file_path),
+        source: e,
+    })?;
+
+    Ok(policy)
+}
+
+/// Find the value of `key` for `profile` in the config file, where sections are named `profile {profile}`
+pub fn find_config_value<'a>(document: &'a Document, profile: &str, key: &str) -> Option<&'a str> {
+    document
+        .get(&format!("profile {profile}"), key)
+        .or_else(|| match profile {
+            "default" => document.get(profile, key),
+            _ => None,
+        })
+}
+
+/// Set the value of `key` for `profile` in the config file, creating the file when missing
+pub fn save_config_value(
+    file_path: &str,
+    profile: &str,
+    key: &str,
+    value: &str,
+) -> Result<(), Error> {
+    let write_error = |e| WriteConfigFileError {
+        path: String::from(file_path),
+        source: e,
+    };
+
+    if let Some(dir) = Path::new(file_path).parent() {
+        create_dir_all(dir).map_err(write_error)?;
+    }
+
+    let _lock = lock_file(file_path)?;
+    let mut document = read_config_file(file_path)?;
+    let section = match profile {
+        "default" if document.sections().contains(&profile) => String::from(profile),
+        _ => format!("profile {profile}"),
+    };
+    document.set(&section, key, value);
+
+    write_file(file_path, &document.to_string()).map_err(write_error)
+}
+
+fn create_parent_dir(path: &Path) -> std::io::Result<()> {
+    let mut dir_builder = DirBuilder::new();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+
+        dir_builder.mode(0o700);
+    }
+
+    match path.parent() {
+        Some(dir) => dir_builder.recursive(true).create(dir),
+        None => Ok(()),
+    }
+}
+
+fn create_credentials_file(path: &Path) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        options.mode(0o600);
+    }
+
+    create_parent_dir(path)?;
+
+    match options.write(true).create_new(true).open(path) {
+        Err(e) if e.kind() != ErrorKind::AlreadyExists => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn check_credentials_file(file_path: &str) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = metadata(file_path)
+        .map_err(|e| ReadCredentialsFileError {
+            path: String::from(file_path),
+            source: e,
+        })?
+        .permissions()
+        .mode();
+
+    if mode & 0o044 != 0 {
+        return Err(InsecureCredentialsFileError {
+            path: String::from(file_path),
+            mode: mode & 0o777,
+        });
+    }
+
     Ok(())
 }
 
+#[cfg(not(unix))]
+fn check_credentials_file(_file_path: &str) -> Result<(), Error> {
+    Ok(())
+}
+
+fn ensure_credentials_file(file_path: &str) -> Result<(), Error> {
+    let path = Path::new(file_path);
+    if !path.exists() {
+        create_credentials_file(path).map_err(|e| WriteCredentialsFileError {
+            path: String::from(file_path),
+            source: e,
+        })?;
+    }
+
+    check_credentials_file(file_path)
+}
+
+fn lock_file(file_path: &str) -> Result<File, Error> {
+    let lock_path = format!("{file_path}.lock");
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(|e| LockFileError {
+            path: lock_path.clone(),
+            source: e,
+        })?;
+
+    file.lock().map_err(|e| LockFileError {
+        path: lock_path,
+        source: e,
+    })?;
+
+    Ok(file)
+}
+
+fn write_atomically(path: &Path, temp_path: &Path, content: &str) -> std::io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(temp_path)?;
+
+    if let Ok(metadata) = metadata(path) {
+        set_permissions(temp_path, metadata.permissions())?;
+    }
+
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+
+    rename(temp_path, path)
+}
+
+/// Write a file atomically through a temporary file next to it, replacing the target of a symbolic link rather than the link
+fn write_file(file_path: &str, content: &str) -> std::io::Result<()> {
+    let resolved = canonicalize(file_path);
+    let path = resolved.as_deref().unwrap_or(Path::new(file_path));
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    let temp_path = path.with_file_name(format!(".{file_name}.{}.tmp", process::id()));
+
+    write_atomically(path, &temp_path, content).inspect_err(|_| {
+        let _ = remove_file(&temp_path);
+    })
+}
+
+fn write_credentials_file(file_path: &str, content: &str) -> Result<(), Error> {
+    write_file(file_path, content).map_err(|e| WriteCredentialsFileError {
+        path: String::from(file_path),
+        source: e,
+    })
+}
+
+/// Read a secret file such as the seed store, which is treated as missing when absent
+pub fn read_secret_file(file_path: &str) -> Result<Option<String>, Error> {
+    match read_to_string(file_path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(ReadSecretFileError {
+            path: String::from(file_path),
+            source: e,
+        }),
+    }
+}
+
+/// Write a secret file atomically, creating it with owner-only permissions when missing
+pub fn write_secret_file(file_path: &str, content: &str) -> Result<(), Error> {
+    let _lock = lock_secret_file(file_path)?;
+
+    write_locked_secret_file(file_path, content)
+}
+
+/// Lock a secret file until the returned file is dropped, to read and write it without losing concurrent changes
+pub fn lock_secret_file(file_path: &str) -> Result<File, Error> {
+    create_parent_dir(Path::new(file_path)).map_err(|e| WriteSecretFileError {
+        path: String::from(file_path),
+        source: e,
+    })?;
+
+    lock_file(file_path)
+}
+
+/// Write a secret file locked with [`lock_secret_file`]
+pub fn write_locked_secret_file(file_path: &str, content: &str) -> Result<(), Error> {
+    let write_error = |e| WriteSecretFileError {
+        path: String::from(file_path),
+        source: e,
+    };
+
+    create_credentials_file(Path::new(file_path)).map_err(write_error)?;
+
+    write_file(file_path, content).map_err(write_error)
+}
+
+/// Remove a secret file, returning whether it existed
+pub fn remove_secret_file(file_path: &str) -> Result<bool, Error> {
+    match remove_file(file_path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(WriteSecretFileError {
+            path: String::from(file_path),
+            source: e,
+        }),
+    }
+}
+
+/// Read the credentials file, where secret settings such as `mfa_seed` are kept next to the access keys
+pub fn read_auth_credentials_file(file_path: &str) -> Result<Document, Error> {
+    ensure_credentials_file(file_path)?;
+    read_credentials_file(file_path)
+}
+
+pub fn find_auth_credentials(file_path: &str, profile: &str) -> Result<Option<Credentials>, Error> {
+    ensure_credentials_file(file_path)?;
+    let document = read_credentials_file(file_path)?;
+    let credentials = find_credentials(&document, profile);
+
+    Ok(credentials)
+}
+
+pub fn find_auth_sessions(file_path: &str, suffix: &str) -> Result<Vec<Session>, Error> {
+    ensure_credentials_file(file_path)?;
+    let document = read_credentials_file(file_path)?;
+
+    Ok(find_sessions(&document, suffix))
+}
+
+pub fn find_auth_seeds(file_path: &str) -> Result<Vec<SeedEntry>, Error> {
+    ensure_credentials_file(file_path)?;
+    let document = read_credentials_file(file_path)?;
+
+    Ok(find_seeds(&document))
+}
+
+fn update_credentials_file<T>(
+    file_path: &str,
+    update: impl FnOnce(&mut Document) -> T,
+) -> Result<T, Error> {
+    ensure_credentials_file(file_path)?;
+    let _lock = lock_file(file_path)?;
+    let mut document = read_credentials_file(file_path)?;
+    let result = update(&mut document);
+
+    write_credentials_file(file_path, &document.to_string())?;
+
+    Ok(result)
+}
+
+pub fn save_auth_credentials(
+    file_path: &str,
+    profile: &str,
+    credentials: &Credentials,
+) -> Result<(), Error> {
+    update_credentials_file(file_path, |document| {
+        replace_credentials(document, profile, credentials)
+    })
+}
+
+pub fn clean_auth_sessions(
+    file_path: &str,
+    suffix: &str,
+    all: bool,
+    dry_run: bool,
+) -> Result<Vec<Session>, Error> {
+    if dry_run {
+        ensure_credentials_file(file_path)?;
+        let mut document = read_credentials_file(file_path)?;
+
+        return Ok(remove_sessions(&mut document, suffix, all));
+    }
+
+    update_credentials_file(file_path, |document| remove_sessions(document, suffix, all))
+}
+
+pub fn remove_auth_seeds(file_path: &str, profiles: &[String]) -> Result<(), Error> {
+    update_credentials_file(file_path, |document| remove_seeds(document, profiles))
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::{anyhow, Result};
 
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, read_dir, read_to_string, remove_dir_all, write};
+
+    use crate::ini::Document;
     use crate::io::{
-        find_credential_value, find_credentials, replace_credentials, AWS_ACCESS_KEY_ID,
-        AWS_SECRET_ACCESS_KEY, AWS_SESSION_EXPIRATION_TIMESTAMP, AWS_SESSION_TOKEN,
+        find_config_value, find_credentials, find_seeds, find_sessions, remove_seeds,
+        remove_sessions, replace_credentials, write_credentials_file,
     };
+    use crate::Credentials;
 
     #[tokio::test]
-    async fn test_find_credential_value() -> Result<()> {
-        let credentials = "
-
+    async fn test_find_credentials() -> Result<()> {
+        let file_content = "
 [profile-1]
 aws_access_key_id = aws_access_key_id_1
 aws_secret_access_key = aws_secret_access_key_1
 aws_session_token = aws_session_token_1
-aws_session_expiration_timestamp = 1688903647";
+aws_session_expiration_timestamp = 1688903647
 
+[profile-2]
+aws_access_key_id = aws_access_key_id_2
+aws_secret_access_key = aws_secret_access_key_2
+aws_session_token = aws_session_token_2
+aws_session_expiration_timestamp = 1688905806";
+
+        let document = Document::parse(file_content);
+        let credentials = find_credentials(&document, "profile-1")
+            .ok_or_else(|| anyhow!("credentials missing"))?;
+        assert_eq!(credentials.access_key_id(), "aws_access_key_id_1");
+        assert_eq!(credentials.secret_access_key(), "aws_secret_access_key_1");
+        assert_eq!(credentials.session_token(), "aws_session_token_1");
+        assert_eq!(credentials.session_expiration_timestamp(), 1688903647);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_config_value() -> Result<()> {
+        let file_content = "
+[default]
+region = eu-west-1
+
+[profile profile-1-noauth]
+role_arn = arn:aws:iam::123456789012:role/role_name
+source_profile = profile-2-noauth";
+
+        let document = Document::parse(file_content);
         assert_eq!(
-            find_credential_value(credentials, AWS_ACCESS_KEY_ID),
-            Some(String::from("aws_access_key_id_1"))
-        );
-        assert_eq!(
-            find_credential_value(credentials, AWS_SECRET_ACCESS_KEY),
-            Some(String::from("aws_secret_access_key_1"))
+            find_config_value(&document, "default", "region"),
+            Some("eu-west-1")
         );
         assert_eq!(
-            find_credential_value(credentials, AWS_SESSION_TOKEN),
-            Some(String::from("aws_session_token_1"))
+            find_config_value(&document, "profile-1-noauth", "source_profile"),
+            Some("profile-2-noauth")
         );
         assert_eq!(
-            find_credential_value(credentials, AWS_SESSION_EXPIRATION_TIMESTAMP),
-            Some(String::from("1688903647"))
+            find_config_value(&document, "profile-1-noauth", "region"),
+            None
         );
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_find_credentials() -> Result<()> {
+    async fn test_find_sessions() -> Result<()> {
         let file_content = "
-[profile-1]
+[profile-1-noauth]
 aws_access_key_id = aws_access_key_id_1
-aws_secret_access_key = aws_secret_access_key_1
-aws_session_token = aws_session_token_1
+
+[profile-1]
+aws_access_key_id = aws_access_key_id_2
+aws_secret_access_key = aws_secret_access_key_2
+aws_session_token = aws_session_token_2
 aws_session_expiration_timestamp = 1688903647
 
 [profile-2]
+aws_access_key_id = aws_access_key_id_3
+aws_secret_access_key = aws_secret_access_key_3
+aws_session_token = aws_session_token_3
+aws_session_expiration_timestamp = 1688905806
+
+[profile-3]
+aws_access_key_id = aws_access_key_id_4
+aws_secret_access_key = aws_secret_access_key_4";
+
+        let document = Document::parse(file_content);
+        let sessions = find_sessions(&document, "noauth");
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].profile(), "profile-1");
+        assert_eq!(sessions[0].source_profile(), Some("profile-1-noauth"));
+        assert_eq!(
+            sessions[0].credentials().access_key_id(),
+            "aws_access_key_id_2"
+        );
+        assert_eq!(sessions[1].profile(), "profile-2");
+        assert_eq!(sessions[1].source_profile(), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remove_sessions() -> Result<()> {
+        let file_content = "[profile-1-noauth]
+aws_access_key_id = aws_access_key_id_1
+
+[profile-1]
 aws_access_key_id = aws_access_key_id_2
 aws_secret_access_key = aws_secret_access_key_2
 aws_session_token = aws_session_token_2
-aws_session_expiration_timestamp = 1688905806";
+aws_session_expiration_timestamp = 1688903647
 
-        let credentials = find_credentials(file_content, "profile-1")
-            .ok_or_else(|| anyhow!("credentials missing"))?;
-        assert_eq!(credentials.access_key_id(), "aws_access_key_id_1");
-        assert_eq!(credentials.secret_access_key(), "aws_secret_access_key_1");
-        assert_eq!(credentials.session_token(), "aws_session_token_1");
-        assert_eq!(credentials.session_expiration_timestamp(), 1688903647);
+[profile-2]
+region = eu-west-1
+aws_access_key_id = aws_access_key_id_3
+aws_secret_access_key = aws_secret_access_key_3
+aws_session_token = aws_session_token_3
+aws_session_expiration_timestamp = 32503680000
+
+[profile-3]
+aws_access_key_id = aws_access_key_id_4
+";
+
+        let mut document = Document::parse(file_content);
+        let sessions = remove_sessions(&mut document, "noauth", false);
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].profile(), "profile-1");
+        assert_eq!(
+            document.sections(),
+            vec!["profile-1-noauth", "profile-2", "profile-3"]
+        );
+
+        let sessions = remove_sessions(&mut document, "noauth", true);
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].profile(), "profile-2");
+        assert_eq!(
+            document.sections(),
+            vec!["profile-1-noauth", "profile-2", "profile-3"]
+        );
+        assert_eq!(document.keys("profile-2"), vec!["region"]);
+        assert_eq!(document.get("profile-2", "region"), Some("eu-west-1"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_and_remove_seeds() -> Result<()> {
+        let file_content = "[profile-1-noauth]
+aws_access_key_id = aws_access_key_id_1
+mfa_seed = GEZDGNBVGY3TQOJQ
+mfa_digits = 8
+
+[profile-2-noauth]
+aws_access_key_id = aws_access_key_id_2
+";
+
+        let mut document = Document::parse(file_content);
+        let seeds = find_seeds(&document);
+        assert_eq!(seeds.len(), 1);
+        assert_eq!(seeds[0].profile(), "profile-1-noauth");
+        assert_eq!(seeds[0].digits(), Some("8"));
+
+        remove_seeds(&mut document, &[String::from("profile-1-noauth")]);
+        let expected_content = "[profile-1-noauth]
+aws_access_key_id = aws_access_key_id_1
+
+[profile-2-noauth]
+aws_access_key_id = aws_access_key_id_2
+";
+        assert_eq!(document.to_string(), expected_content);
 
         Ok(())
     }
@@ -182,15 +638,19 @@ aws_session_expiration_timestamp = 1688903647
 aws_access_key_id = aws_access_key_id_2
 aws_secret_access_key = aws_secret_access_key_2
 aws_session_token = aws_session_token_2
-aws_session_expiration_timestamp = 1688905806";
-
-        let new_credentials = "
-
-[profile-2]
-aws_access_key_id = aws_access_key_id_3
-aws_secret_access_key = aws_secret_access_key_3
-aws_session_token = aws_session_token_3
-aws_session_expiration_timestamp = 1688905943";
+aws_session_expiration_timestamp = 1688905806
+
+[profile-3-noauth]
+aws_access_key_id = aws_access_key_id_4
+aws_secret_access_key = aws_secret_access_key_4
+";
+
+        let new_credentials = Credentials::new(
+            "aws_access_key_id_3",
+            "aws_secret_access_key_3",
+            "aws_session_token_3",
+            1688905943,
+        );
 
         let expected_content = "
 [profile-1]
@@ -203,10 +663,93 @@ aws_session_expiration_timestamp = 1688903647
 aws_access_key_id = aws_access_key_id_3
 aws_secret_access_key = aws_secret_access_key_3
 aws_session_token = aws_session_token_3
-aws_session_expiration_timestamp = 1688905943";
+aws_session_expiration_timestamp = 1688905943
+
+[profile-3-noauth]
+aws_access_key_id = aws_access_key_id_4
+aws_secret_access_key = aws_secret_access_key_4
+
+[profile-3]
+aws_access_key_id = aws_access_key_id_3
+aws_secret_access_key = aws_secret_access_key_3
+aws_session_token = aws_session_token_3
+aws_session_expiration_timestamp = 1688905943
+";
+
+        let mut document = Document::parse(file_content);
+        replace_credentials(&mut document, "profile-2", &new_credentials);
+        replace_credentials(&mut document, "profile-3", &new_credentials);
+        assert_eq!(document.to_string(), expected_content);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_credentials_file() -> Result<()> {
+        let dir = temp_dir().join(format!("aws-mfa-test-write-{}", std::process::id()));
+        create_dir_all(&dir)?;
+        let file_path = dir.join("credentials");
+        write(&file_path, "[profile-1]\n")?;
+
+        #[cfg(unix)]
+        {
+            use std::fs::{metadata, set_permissions, Permissions};
+            use std::os::unix::fs::PermissionsExt;
+
+            set_permissions(&file_path, Permissions::from_mode(0o600))?;
+            write_credentials_file(&file_path.to_string_lossy(), "[profile-2]\n")?;
+            assert_eq!(metadata(&file_path)?.permissions().mode() & 0o777, 0o600);
+        }
+
+        write_credentials_file(&file_path.to_string_lossy(), "[profile-3]\n")?;
+        assert_eq!(read_to_string(&file_path)?, "[profile-3]\n");
+        assert_eq!(read_dir(&dir)?.count(), 1);
+
+        #[cfg(unix)]
+        {
+            use std::fs::symlink_metadata;
+            use std::os::unix::fs::symlink;
+
+            let link_path = dir.join("link");
+            symlink(&file_path, &link_path)?;
+            write_credentials_file(&link_path.to_string_lossy(), "[profile-4]\n")?;
+            assert!(symlink_metadata(&link_path)?.file_type().is_symlink());
+            assert_eq!(read_to_string(&file_path)?, "[profile-4]\n");
+            assert_eq!(read_dir(&dir)?.count(), 2);
+        }
+
+        remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_ensure_credentials_file() -> Result<()> {
+        use std::fs::{metadata, set_permissions, Permissions};
+        use std::os::unix::fs::PermissionsExt;
+
+        use crate::error::Error::InsecureCredentialsFileError;
+        use crate::io::ensure_credentials_file;
+
+        let dir = temp_dir().join(format!("aws-mfa-test-ensure-{}", std::process::id()));
+        let file_path = dir.join(".aws").join("credentials");
+        ensure_credentials_file(&file_path.to_string_lossy())?;
+
+        assert_eq!(read_to_string(&file_path)?, "");
+        assert_eq!(
+            metadata(dir.join(".aws"))?.permissions().mode() & 0o777,
+            0o700
+        );
+        assert_eq!(metadata(&file_path)?.permissions().mode() & 0o777, 0o600);
+
+        set_permissions(&file_path, Permissions::from_mode(0o644))?;
+        assert!(matches!(
+            ensure_credentials_file(&file_path.to_string_lossy()),
+            Err(InsecureCredentialsFileError { mode: 0o644, .. })
+        ));
 
-        let new_content = replace_credentials(file_content, "profile-2", new_credentials);
-        assert_eq!(new_content, expected_content);
+        remove_dir_all(&dir)?;
 
         Ok(())
     }
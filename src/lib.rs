@@ -1,21 +1,78 @@
-use std::time::SystemTime;
+use std::future::Future;
+use std::slice::from_ref;
+use std::str::FromStr;
+use std::time::{Duration as StdDuration, Instant, SystemTime};
 
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use aws_config::retry::RetryMode;
+use aws_config::SdkConfig;
+use aws_credential_types::provider::SharedCredentialsProvider;
+use aws_sdk_iam::Client as IamClient;
+use aws_sdk_sts::Client;
+use aws_smithy_runtime_api::client::http::SharedHttpClient;
 use time::{Duration, OffsetDateTime};
+use tokio::time::sleep;
 
-use crate::config::{get_env_config, get_env_provider, get_file_config, get_file_provider};
-use crate::env::get_env_credentials;
+use crate::arn::Arn;
+use crate::command::CodeCommand;
+use crate::config::{
+    get_config_file, get_credentials_file, get_env_config, get_env_provider, get_file_config,
+    get_file_provider, get_profile_config, get_profile_provider, get_seed_store_file,
+    get_used_codes_file, ProfileSettings, DURATION_SECONDS, EXTERNAL_ID, MFA_CODE_COMMAND,
+    MFA_DEVICE_PREFERENCE, MFA_SEED, MFA_SERIAL, ROLE_ARN, ROLE_SESSION_NAME, SOURCE_PROFILE,
+};
+use crate::env::{get_env_credentials, get_env_totp};
 use crate::error::Error;
-use crate::error::Error::{ConvertSessionTimestampError, Other};
-use crate::io::{find_auth_credentials, save_auth_credentials};
-use crate::sts::{get_auth_credentials, get_client, get_mfa_device_arn};
+use crate::error::Error::{
+    AccessDenied, ConvertSessionTimestampError, DurationOutOfRange, InvalidDuration,
+    InvalidMfaCodeFormat, MfaCodeAlreadyUsed, MissingCode, MissingMfaDevice, MissingMfaSeed,
+    MultipleMfaDevices, Other, UnsupportedMfaDevice,
+};
+use crate::iam::{
+    find_preferred_device, get_iam_client, get_max_session_duration, is_fido_device,
+    list_mfa_devices,
+};
+use crate::io::{
+    clean_auth_sessions, find_auth_credentials, find_auth_seeds, find_auth_sessions,
+    read_auth_credentials_file, read_policy_file, remove_auth_seeds, save_auth_credentials,
+};
+use crate::otpauth::read_otpauth;
+use crate::state::UsedCodes;
+use crate::sts::{
+    get_auth_credentials, get_caller_arn, get_client, get_federation_credentials,
+    get_mfa_device_arn, get_role_credentials,
+};
+use crate::totp::{get_totp, Totp};
 
+mod arn;
+mod command;
 mod config;
 mod env;
 pub mod error;
+mod exec;
+mod iam;
+mod ini;
 mod io;
+mod otpauth;
+mod shell;
+mod state;
+mod store;
 mod sts;
+mod totp;
+
+pub use crate::exec::{exec_command, exit_code, wait_child};
+pub use crate::shell::EnvFormat;
+pub use crate::store::{SeedEntry, SeedStore, AWS_MFA_KEY_FILE};
+
+const DEFAULT_DURATION: i32 = 3600;
+const MIN_DURATION: i32 = 900;
+const MAX_SESSION_DURATION: i32 = 129600;
+const MAX_ROLE_DURATION: i32 = 43200;
+const MAX_ROOT_DURATION: i32 = 3600;
+const MAX_CHAINED_ROLE_DURATION: i32 = 3600;
+const MFA_CODE_WINDOW: StdDuration = StdDuration::from_secs(30);
+const MFA_CODE_LENGTH: usize = 6;
 
 /// Credentials received after authenticating to AWS with MFA
 pub struct Credentials {
@@ -80,50 +137,786 @@ impl Credentials {
     }
 }
 
+/// Session written in the credentials file after authenticating to AWS with MFA
+pub struct Session {
+    profile: String,
+    source_profile: Option<String>,
+    credentials: Credentials,
+}
+
+impl Session {
+    pub fn new(profile: &str, source_profile: Option<String>, credentials: Credentials) -> Self {
+        Self {
+            profile: String::from(profile),
+            source_profile,
+            credentials,
+        }
+    }
+
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    pub fn source_profile(&self) -> Option<&str> {
+        self.source_profile.as_deref()
+    }
+
+    pub fn credentials(&self) -> &Credentials {
+        &self.credentials
+    }
+}
+
+/// MFA code generated from a seed, with the time it remains valid for
+pub struct MfaCode {
+    code: String,
+    remaining: Duration,
+}
+
+impl MfaCode {
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.remaining
+    }
+}
+
+/// Locations of the AWS config and credentials files, and of the seed store
+pub struct ProfileFiles {
+    config: String,
+    credentials: String,
+    seed_store: String,
+    used_codes: String,
+}
+
+impl ProfileFiles {
+    /// Resolve file locations from explicit paths first, then `AWS_CONFIG_FILE` and `AWS_SHARED_CREDENTIALS_FILE`, then `{home}/.aws`
+    ///
+    /// The seed store resolves from `AWS_MFA_SEED_STORE`, then `$XDG_CONFIG_HOME/aws-mfa` or `{home}/.config/aws-mfa`,
+    /// and the state of used MFA codes from `$XDG_STATE_HOME/aws-mfa` or `{home}/.local/state/aws-mfa`.
+    pub fn new(
+        home: &str,
+        config_file: Option<String>,
+        credentials_file: Option<String>,
+        seed_store_file: Option<String>,
+    ) -> Self {
+        Self {
+            config: get_config_file(home, config_file),
+            credentials: get_credentials_file(home, credentials_file),
+            seed_store: get_seed_store_file(home, seed_store_file),
+            used_codes: get_used_codes_file(home),
+        }
+    }
+
+    pub fn config(&self) -> &str {
+        &self.config
+    }
+
+    pub fn credentials(&self) -> &str {
+        &self.credentials
+    }
+
+    /// State file of used MFA codes, to be passed to [`AuthOptions`]
+    pub fn used_codes(&self) -> &str {
+        &self.used_codes
+    }
+
+    /// Seed store, unlocked with the cached key of `AWS_MFA_KEY_FILE` or a passphrase from `prompt`
+    pub fn seed_store(&self, prompt: Option<PassphrasePrompt>) -> SeedStore {
+        SeedStore::new(&self.seed_store, prompt)
+    }
+
+    /// TOTP generator of the first of `profiles` with a seed in the seed store, then with `mfa_seed` in the credentials file
+    ///
+    /// A warning is printed for seeds found in plaintext, which `migrate_seeds` moves into the seed store.
+    fn totp(
+        &self,
+        profiles: &[String],
+        prompt: Option<PassphrasePrompt>,
+    ) -> Result<Option<Totp>, Error> {
+        if let Some(totp) = self.seed_store(prompt).totp(profiles)? {
+            return Ok(Some(totp));
+        }
+
+        let document = read_auth_credentials_file(&self.credentials)?;
+        let Some(profile) = profiles
+            .iter()
+            .find(|p| document.get(p, MFA_SEED).is_some())
+        else {
+            return Ok(None);
+        };
+        eprintln!("Found plaintext `mfa_seed` in profile `{profile}` of credentials file, move it to the seed store with `aws-mfa seed migrate`.");
+
+        get_totp(|key| document.get(profile, key).map(String::from))
+    }
+
+    /// Move every plaintext `mfa_seed` of the credentials file into the seed store, with its TOTP settings
+    ///
+    /// Returns the profiles of moved seeds, then of seeds only removed since the seed store already had one for their profile.
+    pub fn migrate_seeds(
+        &self,
+        prompt: Option<PassphrasePrompt>,
+    ) -> Result<(Vec<String>, Vec<String>), Error> {
+        let entries = find_auth_seeds(&self.credentials)?;
+        if entries.is_empty() {
+            return Ok((Vec::new(), Vec::new()));
+        }
+
+        let profiles = entries
+            .iter()
+            .map(|e| String::from(e.profile()))
+            .collect::<Vec<_>>();
+        let moved = self.seed_store(prompt).add_missing(entries)?;
+        remove_auth_seeds(&self.credentials, &profiles)?;
+        let kept = profiles
+            .into_iter()
+            .filter(|p| !moved.contains(p))
+            .collect();
+
+        Ok((moved, kept))
+    }
+
+    /// Import the seed of an MFA device into the seed store for the `{profile}-{suffix}` profile
+    ///
+    /// `input` is an `otpauth://totp/` URI, a Base32 seed, or the path of a PNG or JPEG image of its QR code.
+    /// The MFA device ARN is linked to the seed and saved as `mfa_serial` in profile settings, so that it is not discovered with IAM.
+    pub fn import_seed(
+        &self,
+        profile: &str,
+        suffix: &str,
+        input: &str,
+        mfa_serial: Option<String>,
+        prompt: Option<PassphrasePrompt>,
+    ) -> Result<SeedEntry, Error> {
+        let name = format!("{profile}-{suffix}");
+        let otpauth = read_otpauth(input)?;
+        let settings = ProfileSettings::load(self, &[name.clone(), String::from(profile)])?;
+        let linked = mfa_serial.clone().or_else(|| settings.get(MFA_SERIAL));
+        let entry = SeedEntry::new(
+            &name,
+            &otpauth.seed,
+            otpauth.algorithm,
+            otpauth.digits,
+            otpauth.period,
+            linked,
+            otpauth.issuer,
+        );
+        self.seed_store(prompt).add(entry.clone())?;
+
+        if let Some(arn) = mfa_serial {
+            settings.save(MFA_SERIAL, &arn)?;
+        }
+
+        Ok(entry)
+    }
+
+    /// Generate the current MFA code with the seed of the `{profile}-{suffix}` profile in the seed store or the credentials file
+    pub fn mfa_code(
+        &self,
+        profile: &str,
+        suffix: &str,
+        prompt: Option<PassphrasePrompt>,
+    ) -> Result<MfaCode, Error> {
+        let totp = self
+            .totp(&[format!("{profile}-{suffix}")], prompt)?
+            .ok_or(MissingMfaSeed)?;
+        let (code, remaining) = totp.now()?;
+
+        Ok(MfaCode {
+            code,
+            remaining: Duration::seconds(remaining as i64),
+        })
+    }
+
+    /// Find every [`Session`] in the credentials file, paired with its `{profile}-{suffix}` source profile
+    pub fn sessions(&self, suffix: &str) -> Result<Vec<Session>, Error> {
+        find_auth_sessions(&self.credentials, suffix)
+    }
+
+    /// Remove expired sessions (or every session with `all`) from the credentials file and return them
+    ///
+    /// Source `{profile}-{suffix}` profiles and profiles without a session are never removed.
+    pub fn clean_sessions(
+        &self,
+        suffix: &str,
+        all: bool,
+        dry_run: bool,
+    ) -> Result<Vec<Session>, Error> {
+        clean_auth_sessions(&self.credentials, suffix, all, dry_run)
+    }
+}
+
+/// Requested session duration, either in seconds or the longest allowed for the principal and operation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SessionDuration {
+    Seconds(i32),
+    Max,
+}
+
+impl SessionDuration {
+    /// Session duration from options, then `duration_seconds` in profile settings, then one hour
+    fn resolve(duration: Option<Self>, settings: &ProfileSettings) -> Result<Self, Error> {
+        match duration {
+            Some(duration) => Ok(duration),
+            None => Ok(settings
+                .parse(DURATION_SECONDS)?
+                .unwrap_or(Self::Seconds(DEFAULT_DURATION))),
+        }
+    }
+
+    /// Duration in seconds, failing when out of the `MIN_DURATION..=max` range
+    fn seconds(self, max: i32) -> Result<i32, Error> {
+        match self {
+            Self::Max => Ok(max),
+            Self::Seconds(seconds) if (MIN_DURATION..=max).contains(&seconds) => Ok(seconds),
+            Self::Seconds(seconds) => Err(DurationOutOfRange {
+                duration: seconds,
+                min: MIN_DURATION,
+                max,
+            }),
+        }
+    }
+}
+
+/// Parse `max`, seconds, or a sum of hours, minutes and seconds like `12h` or `1h30m`
+impl FromStr for SessionDuration {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidDuration(String::from(s));
+        if s.eq_ignore_ascii_case("max") {
+            return Ok(Self::Max);
+        }
+
+        if let Ok(seconds) = s.parse::<i32>() {
+            return Ok(Self::Seconds(seconds));
+        }
+
+        let mut seconds: i32 = 0;
+        let mut number = String::new();
+        for c in s.chars() {
+            let unit = match c {
+                '0'..='9' => {
+                    number.push(c);
+                    continue;
+                }
+                'h' => 3600,
+                'm' => 60,
+                's' => 1,
+                _ => return Err(invalid()),
+            };
+            seconds = number
+                .parse::<i32>()
+                .ok()
+                .and_then(|n| n.checked_mul(unit))
+                .and_then(|n| n.checked_add(seconds))
+                .ok_or_else(invalid)?;
+            number.clear();
+        }
+
+        if s.is_empty() || !number.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self::Seconds(seconds))
+    }
+}
+
+/// Exponential backoff after a failed `attempt`, saturating instead of overflowing and capped by `max`
+fn backoff(initial: StdDuration, max: StdDuration, attempt: u32) -> StdDuration {
+    initial
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(max)
+}
+
+/// Wait for a code of the generator other than `previous`
+async fn next_code(totp: &Totp, previous: &str) -> Result<String, Error> {
+    loop {
+        let (code, remaining) = totp.now()?;
+        if code != previous {
+            return Ok(code);
+        }
+
+        sleep(StdDuration::from_secs(remaining)).await;
+    }
+}
+
+/// Fetch a code with the command in the next time step, or none when it prints `previous` again
+async fn next_fetched_code(
+    command: &CodeCommand,
+    arn: &str,
+    previous: &str,
+) -> Result<Option<String>, Error> {
+    let elapsed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let window = MFA_CODE_WINDOW.as_secs();
+    sleep(StdDuration::from_secs(window - elapsed % window)).await;
+
+    let code = command.run(arn).await?;
+
+    Ok((code != previous).then_some(code))
+}
+
+/// Function picking one of several discovered MFA devices, or none to give up
+pub type MfaDeviceChooser = fn(&[String]) -> Option<String>;
+
+/// Check that an MFA code has 6 digits, since STS rejects any other code
+fn check_code(code: &str) -> Result<(), Error> {
+    match code.len() == MFA_CODE_LENGTH && code.chars().all(|c| c.is_ascii_digit()) {
+        true => Ok(()),
+        false => Err(InvalidMfaCodeFormat),
+    }
+}
+
+/// Function prompting for an MFA code with the given message, or none to give up
+pub type MfaCodePrompt = fn(&str) -> Option<String>;
+
+/// Function prompting for a passphrase with the given message, or none to give up
+pub type PassphrasePrompt = fn(&str) -> Option<String>;
+
+/// Options for getting MFA codes, given explicitly, fetched with an external command or prompted for
+pub struct MfaCodeOptions {
+    code: Option<String>,
+    command: Option<String>,
+    prompt: Option<MfaCodePrompt>,
+}
+
+impl MfaCodeOptions {
+    pub fn new(
+        code: Option<String>,
+        command: Option<String>,
+        prompt: Option<MfaCodePrompt>,
+    ) -> Self {
+        Self {
+            code,
+            command,
+            prompt,
+        }
+    }
+}
+
+/// Source of MFA codes for requests to AWS
+enum CodeSource<'a> {
+    /// Code from options or prompted for, with the time it was issued
+    Given(String, Instant),
+    Totp(&'a Totp),
+    Command(CodeCommand),
+}
+
+/// Options for authenticating to AWS with MFA shared by all providers
+pub struct AuthOptions {
+    mfa_code: MfaCodeOptions,
+    identifier: Option<String>,
+    mfa_serial: Option<String>,
+    duration: Option<SessionDuration>,
+    chooser: Option<MfaDeviceChooser>,
+    passphrase: Option<PassphrasePrompt>,
+    used_codes: Option<UsedCodes>,
+    issued: Instant,
+}
+
+impl AuthOptions {
+    pub fn new(
+        mfa_code: MfaCodeOptions,
+        identifier: Option<String>,
+        mfa_serial: Option<String>,
+        duration: Option<SessionDuration>,
+        chooser: Option<MfaDeviceChooser>,
+        passphrase: Option<PassphrasePrompt>,
+        used_codes: Option<String>,
+    ) -> Self {
+        Self {
+            mfa_code,
+            identifier,
+            mfa_serial,
+            duration,
+            chooser,
+            passphrase,
+            used_codes: used_codes.map(|path| UsedCodes::new(&path)),
+            issued: Instant::now(),
+        }
+    }
+
+    /// TOTP generator of the first of `profiles` with a seed, only looked up without a code or a command in options
+    /// so the seed store stays locked
+    fn totp(&self, files: &ProfileFiles, profiles: &[String]) -> Result<Option<Totp>, Error> {
+        match (&self.mfa_code.code, &self.mfa_code.command) {
+            (None, None) => files.totp(profiles, self.passphrase),
+            _ => Ok(None),
+        }
+    }
+
+    /// TOTP generator of `AWS_MFA_SEED`, only parsed without a code or a command in options
+    fn env_totp(&self) -> Result<Option<Totp>, Error> {
+        match (&self.mfa_code.code, &self.mfa_code.command) {
+            (None, None) => get_env_totp(),
+            _ => Ok(None),
+        }
+    }
+
+    /// MFA code from options, then fetched with the command from options, then generated with the TOTP generator of the profile,
+    /// then fetched with `mfa_code_command` from profile settings, then prompted for
+    fn code_source<'a>(
+        &self,
+        totp: Option<&'a Totp>,
+        settings: &ProfileSettings,
+        profile: Option<&str>,
+    ) -> Result<CodeSource<'a>, Error> {
+        if let Some(code) = &self.mfa_code.code {
+            check_code(code)?;
+            return Ok(CodeSource::Given(code.clone(), self.issued));
+        }
+
+        if let Some(command) = &self.mfa_code.command {
+            return Ok(CodeSource::Command(CodeCommand::new(command, profile)));
+        }
+
+        if let Some(totp) = totp {
+            return Ok(CodeSource::Totp(totp));
+        }
+
+        if let Some(command) = settings.get(MFA_CODE_COMMAND) {
+            return Ok(CodeSource::Command(CodeCommand::new(&command, profile)));
+        }
+
+        match self.prompt_code("Enter MFA code") {
+            Some(code) => Ok(CodeSource::Given(code, Instant::now())),
+            None => Err(MissingCode),
+        }
+    }
+
+    /// Prompt for an MFA code until one has the expected format, or none to give up
+    fn prompt_code(&self, message: &str) -> Option<String> {
+        let prompt = self.mfa_code.prompt?;
+        let mut code = prompt(message)?;
+        while check_code(&code).is_err() {
+            code = prompt("Invalid MFA code, expected 6 digits, enter MFA code again")?;
+        }
+
+        Some(code)
+    }
+
+    /// Replace a code already used with the MFA device `arn`
+    ///
+    /// A generated code is replaced by the code of the next time step, a fetched code is fetched again in the next time step,
+    /// and a given code is prompted for again, or fails with [`MfaCodeAlreadyUsed`](Error::MfaCodeAlreadyUsed).
+    async fn unused_code(
+        &self,
+        arn: &str,
+        source: &CodeSource<'_>,
+        mut code: String,
+    ) -> Result<String, Error> {
+        let Some(used_codes) = &self.used_codes else {
+            return Ok(code);
+        };
+
+        while used_codes.contains(arn, &code)? {
+            code = match source {
+                CodeSource::Totp(totp) => next_code(totp, &code).await?,
+                CodeSource::Command(command) => next_fetched_code(command, arn, &code)
+                    .await?
+                    .ok_or_else(|| MfaCodeAlreadyUsed(String::from(arn)))?,
+                CodeSource::Given(..) => self
+                    .prompt_code(&format!(
+                        "MFA code already used, enter the next MFA code for {arn}"
+                    ))
+                    .ok_or_else(|| MfaCodeAlreadyUsed(String::from(arn)))?,
+            };
+        }
+
+        Ok(code)
+    }
+
+    /// Send a request carrying the MFA code, retrying transient failures up to `max_attempts`
+    ///
+    /// The same code is only resent while fresh and when the failed request never reached AWS,
+    /// otherwise a new code is generated or fetched again in the next time step, or prompted for, since STS rejects a code it already consumed.
+    async fn send_with_code<F, Fut>(
+        &self,
+        config: &SdkConfig,
+        arn: &str,
+        source: &CodeSource<'_>,
+        send: F,
+    ) -> Result<Credentials, Error>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<Credentials, Error>>,
+    {
+        let (max_attempts, initial_backoff, max_backoff) = match config.retry_config() {
+            Some(retry) => (
+                retry.max_attempts(),
+                retry.initial_backoff(),
+                retry.max_backoff(),
+            ),
+            None => (1, StdDuration::ZERO, StdDuration::ZERO),
+        };
+        let (mut code, mut issued) = match source {
+            CodeSource::Given(code, issued) => (code.clone(), *issued),
+            CodeSource::Totp(totp) => (totp.now()?.0, Instant::now()),
+            CodeSource::Command(command) => (command.run(arn).await?, Instant::now()),
+        };
+        let mut attempt = 1;
+
+        loop {
+            let next = self.unused_code(arn, source, code.clone()).await?;
+            if next != code {
+                (code, issued) = (next, Instant::now());
+            }
+            let error = match send(code.clone()).await {
+                Ok(credentials) => {
+                    if let Some(used_codes) = &self.used_codes {
+                        // Credentials are valid even when the code could not be recorded
+                        used_codes.record(arn, &code).ok();
+                    }
+
+                    return Ok(credentials);
+                }
+                Err(error) if attempt < max_attempts && error.is_transient() => error,
+                Err(error) => return Err(error),
+            };
+
+            sleep(backoff(initial_backoff, max_backoff, attempt)).await;
+            attempt += 1;
+
+            let generated = matches!(source, CodeSource::Totp(_));
+            if !generated && error.is_unprocessed() && issued.elapsed() < MFA_CODE_WINDOW {
+                continue;
+            }
+
+            code = match source {
+                CodeSource::Totp(totp) if error.is_unprocessed() => totp.now()?.0,
+                CodeSource::Totp(totp) => next_code(totp, &code).await?,
+                CodeSource::Command(command) => match next_fetched_code(command, arn, &code).await?
+                {
+                    Some(code) => code,
+                    None => return Err(error),
+                },
+                CodeSource::Given(..) => match self.prompt_code(&format!(
+                    "MFA code expired or already used, enter a new MFA code for {arn}"
+                )) {
+                    Some(code) => code,
+                    None => return Err(error),
+                },
+            };
+            issued = Instant::now();
+        }
+    }
+
+    fn duration(&self, settings: &ProfileSettings) -> Result<SessionDuration, Error> {
+        SessionDuration::resolve(self.duration, settings)
+    }
+
+    /// MFA device ARN from options, then `mfa_serial` in profile settings, then discovered with IAM
+    ///
+    /// A discovered device is saved as `mfa_serial` in profile settings so that later runs skip the lookup.
+    /// When the caller is not allowed to list its MFA devices, the ARN is derived from caller identity instead.
+    async fn mfa_device_arn(
+        &self,
+        client: &Client,
+        iam: &IamClient,
+        settings: &ProfileSettings,
+    ) -> Result<String, Error> {
+        if let Some(arn) = self.mfa_serial.clone() {
+            return Ok(arn);
+        }
+
+        if self.identifier.is_some() {
+            return get_mfa_device_arn(client, self.identifier.clone()).await;
+        }
+
+        if let Some(arn) = settings.get(MFA_SERIAL) {
+            return Ok(arn);
+        }
+
+        let devices = match list_mfa_devices(iam).await {
+            Ok(devices) => devices,
+            Err(AccessDenied(_)) => return get_mfa_device_arn(client, None).await,
+            Err(e) => return Err(e),
+        };
+        let arn = self.choose_mfa_device(&devices, settings)?;
+        if let Some(profile) = settings.save(MFA_SERIAL, &arn)? {
+            eprintln!(
+                "Saved MFA device {arn} as `mfa_serial` in profile `{profile}` of config file."
+            );
+        }
+
+        Ok(arn)
+    }
+
+    /// Choose the device matching `mfa_device_preference` in profile settings, the only supported device, or the one picked by the chooser
+    fn choose_mfa_device(
+        &self,
+        devices: &[String],
+        settings: &ProfileSettings,
+    ) -> Result<String, Error> {
+        if let Some(arn) = settings
+            .get(MFA_DEVICE_PREFERENCE)
+            .and_then(|p| find_preferred_device(devices, &p))
+        {
+            return match is_fido_device(&arn) {
+                true => Err(UnsupportedMfaDevice(arn)),
+                false => Ok(arn),
+            };
+        }
+
+        let supported = devices
+            .iter()
+            .filter(|d| !is_fido_device(d))
+            .cloned()
+            .collect::<Vec<String>>();
+
+        match &supported[..] {
+            [] => Err(devices
+                .first()
+                .map_or(MissingMfaDevice, |d| UnsupportedMfaDevice(d.clone()))),
+            [arn] => Ok(arn.clone()),
+            _ => self
+                .chooser
+                .and_then(|choose| choose(&supported))
+                .ok_or(MultipleMfaDevices(supported)),
+        }
+    }
+}
+
+/// Options for the AWS clients shared by all providers, overriding config files and environment variables
+pub struct ClientOptions {
+    region: Option<String>,
+    endpoint_url: Option<String>,
+    use_fips: Option<bool>,
+    use_dual_stack: Option<bool>,
+    timeouts: TimeoutOptions,
+    retries: RetryOptions,
+    http_client: Option<SharedHttpClient>,
+}
+
+impl ClientOptions {
+    pub fn new(
+        region: Option<String>,
+        endpoint_url: Option<String>,
+        use_fips: Option<bool>,
+        use_dual_stack: Option<bool>,
+        timeouts: TimeoutOptions,
+        retries: RetryOptions,
+    ) -> Self {
+        Self {
+            region,
+            endpoint_url,
+            use_fips,
+            use_dual_stack,
+            timeouts,
+            retries,
+            http_client: None,
+        }
+    }
+}
+
+/// Timeouts of the AWS clients in seconds, overriding `connect_timeout`, `read_timeout` and `operation_timeout` in profile settings
+#[derive(Default)]
+pub struct TimeoutOptions {
+    connect: Option<u64>,
+    read: Option<u64>,
+    operation: Option<u64>,
+}
+
+impl TimeoutOptions {
+    pub fn new(connect: Option<u64>, read: Option<u64>, operation: Option<u64>) -> Self {
+        Self {
+            connect,
+            read,
+            operation,
+        }
+    }
+}
+
+/// Retry policy of the AWS clients, overriding `max_attempts` and `retry_mode` in profile settings
+#[derive(Default)]
+pub struct RetryOptions {
+    max_attempts: Option<u32>,
+    mode: Option<RetryMode>,
+}
+
+impl RetryOptions {
+    pub fn new(max_attempts: Option<u32>, mode: Option<RetryMode>) -> Self {
+        Self { max_attempts, mode }
+    }
+}
+
 #[async_trait]
 pub trait CredentialsProvider {
     async fn validate(&self) -> Result<Option<Credentials>, Error>;
     async fn authenticate(&self) -> Result<Credentials, Error>;
 }
 
+/// Longest session duration of GetSessionToken, which is one hour for the root user
+///
+/// The caller is only looked up when the requested `duration` exceeds one hour.
+async fn max_session_duration(client: &Client, duration: SessionDuration) -> Result<i32, Error> {
+    if matches!(duration, SessionDuration::Seconds(seconds) if seconds <= MAX_ROOT_DURATION) {
+        return Ok(MAX_SESSION_DURATION);
+    }
+
+    match get_caller_arn(client).await?.parse::<Arn>()?.is_root() {
+        true => Ok(MAX_ROOT_DURATION),
+        false => Ok(MAX_SESSION_DURATION),
+    }
+}
+
 /// Provider for authenticating to AWS with MFA using config and credentials files
 pub struct FileCredentialsProvider {
-    code: String,
-    home: String,
-    region: Option<String>,
+    auth: AuthOptions,
+    files: ProfileFiles,
+    client: ClientOptions,
     profile: String,
     suffix: String,
-    identifier: Option<String>,
-    duration: i32,
 }
 
 impl FileCredentialsProvider {
     pub fn new(
-        code: &str,
-        home: &str,
-        region: Option<String>,
+        auth: AuthOptions,
+        files: ProfileFiles,
+        client: ClientOptions,
         profile: &str,
         suffix: &str,
-        identifier: Option<String>,
-        duration: i32,
     ) -> Self {
         Self {
-            code: String::from(code),
-            home: String::from(home),
-            region,
+            auth,
+            files,
+            client,
             profile: String::from(profile),
             suffix: String::from(suffix),
-            identifier,
-            duration,
         }
     }
+
+    /// Region from client options, then the `{profile}-{suffix}` profile, then environment variables
+    pub async fn region(&self) -> Result<Option<String>, Error> {
+        let settings = ProfileSettings::load(
+            &self.files,
+            &[
+                format!("{}-{}", self.profile, self.suffix),
+                self.profile.clone(),
+            ],
+        )?;
+        let config = get_file_config(
+            &self.files,
+            &self.client,
+            &settings,
+            &self.profile,
+            &self.suffix,
+        )
+        .await?;
+
+        Ok(config.region().map(|region| region.to_string()))
+    }
 }
 
 #[async_trait]
 impl CredentialsProvider for FileCredentialsProvider {
     /// Validate and return current [`Credentials`] from credentials file unless expired
     async fn validate(&self) -> Result<Option<Credentials>, Error> {
-        if let Some(credentials) = find_auth_credentials(&self.home, &self.profile)? {
+        if let Some(credentials) = find_auth_credentials(self.files.credentials(), &self.profile)? {
             if !credentials.expired() {
                 return Ok(Some(credentials));
             }
@@ -133,15 +926,169 @@ impl CredentialsProvider for FileCredentialsProvider {
     }
 
     /// Authenticate using [`aws_config::profile::ProfileFileCredentialsProvider`] and return new [`Credentials`]
+    ///
+    /// `mfa_serial` and `duration_seconds` are read from the `{profile}-{suffix}` profile, then the `{profile}` profile.
     async fn authenticate(&self) -> Result<Credentials, Error> {
-        let config =
-            get_file_config(&self.home, self.region.clone(), &self.profile, &self.suffix).await;
-        let provider = get_file_provider(&self.profile, &self.suffix);
-        let client = get_client(&config, provider);
-        let arn = get_mfa_device_arn(&client, self.identifier.clone()).await?;
-        let credentials = get_auth_credentials(&client, &arn, &self.code, self.duration).await?;
+        let totp = self
+            .auth
+            .totp(&self.files, &[format!("{}-{}", self.profile, self.suffix)])?;
+        let settings = ProfileSettings::load(
+            &self.files,
+            &[
+                format!("{}-{}", self.profile, self.suffix),
+                self.profile.clone(),
+            ],
+        )?;
+        let source = self
+            .auth
+            .code_source(totp.as_ref(), &settings, Some(&self.profile))?;
+        let duration = self.auth.duration(&settings)?;
+        duration.seconds(MAX_SESSION_DURATION)?;
+        let config = get_file_config(
+            &self.files,
+            &self.client,
+            &settings,
+            &self.profile,
+            &self.suffix,
+        )
+        .await?;
+        let provider = SharedCredentialsProvider::new(get_file_provider(
+            &self.files,
+            &self.profile,
+            &self.suffix,
+        ));
+        let client = get_client(
+            &config,
+            self.client.endpoint_url.as_deref(),
+            provider.clone(),
+        )?;
+        let iam = get_iam_client(&config, provider);
+        let arn = self.auth.mfa_device_arn(&client, &iam, &settings).await?;
+        let duration = duration.seconds(max_session_duration(&client, duration).await?)?;
+        let credentials = self
+            .auth
+            .send_with_code(&config, &arn, &source, |code| {
+                get_auth_credentials(&client, &arn, code, duration)
+            })
+            .await?;
 
-        save_auth_credentials(&self.home, &self.profile, &credentials)?;
+        save_auth_credentials(self.files.credentials(), &self.profile, &credentials)?;
+
+        Ok(credentials)
+    }
+}
+
+/// Provider for assuming a role with MFA using `role_arn`, `source_profile` and `mfa_serial` from the config file
+pub struct RoleCredentialsProvider {
+    auth: AuthOptions,
+    files: ProfileFiles,
+    client: ClientOptions,
+    profile: String,
+    suffix: String,
+}
+
+impl RoleCredentialsProvider {
+    pub fn new(
+        auth: AuthOptions,
+        files: ProfileFiles,
+        client: ClientOptions,
+        profile: &str,
+        suffix: &str,
+    ) -> Self {
+        Self {
+            auth,
+            files,
+            client,
+            profile: String::from(profile),
+            suffix: String::from(suffix),
+        }
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for RoleCredentialsProvider {
+    /// Validate and return current [`Credentials`] from credentials file unless expired
+    async fn validate(&self) -> Result<Option<Credentials>, Error> {
+        if let Some(credentials) = find_auth_credentials(self.files.credentials(), &self.profile)? {
+            if !credentials.expired() {
+                return Ok(Some(credentials));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Assume the role configured in the `{profile}-{suffix}` profile using credentials of its `source_profile` and return new [`Credentials`]
+    async fn authenticate(&self) -> Result<Credentials, Error> {
+        let settings =
+            ProfileSettings::load(&self.files, &[format!("{}-{}", self.profile, self.suffix)])?;
+        let role_arn = settings.require(ROLE_ARN)?;
+        let source_profile = settings.require(SOURCE_PROFILE)?;
+        let totp = self.auth.totp(
+            &self.files,
+            &[
+                format!("{}-{}", self.profile, self.suffix),
+                source_profile.clone(),
+            ],
+        )?;
+        let source = self
+            .auth
+            .code_source(totp.as_ref(), &settings, Some(&self.profile))?;
+        let role_session_name = settings
+            .get(ROLE_SESSION_NAME)
+            .unwrap_or_else(|| format!("aws-mfa-{}", OffsetDateTime::now_utc().unix_timestamp()));
+        let chained = ProfileSettings::load(&self.files, from_ref(&source_profile))?
+            .get(ROLE_ARN)
+            .is_some();
+        let requested = self.auth.duration(&settings)?;
+        let mut duration = requested.seconds(match chained {
+            true => MAX_CHAINED_ROLE_DURATION,
+            false => MAX_ROLE_DURATION,
+        })?;
+
+        let config = get_file_config(
+            &self.files,
+            &self.client,
+            &settings,
+            &self.profile,
+            &self.suffix,
+        )
+        .await?;
+        let provider =
+            SharedCredentialsProvider::new(get_profile_provider(&self.files, &source_profile));
+        let client = get_client(
+            &config,
+            self.client.endpoint_url.as_deref(),
+            provider.clone(),
+        )?;
+        let iam = get_iam_client(&config, provider);
+        if requested == SessionDuration::Max && !chained {
+            duration = match get_max_session_duration(&iam, &role_arn).await {
+                Ok(duration) => duration,
+                Err(e) => {
+                    eprintln!("Could not get the maximum session duration of role {role_arn} ({e}), using {DEFAULT_DURATION} seconds.");
+                    DEFAULT_DURATION
+                }
+            };
+        }
+        let arn = self.auth.mfa_device_arn(&client, &iam, &settings).await?;
+        let external_id = settings.get(EXTERNAL_ID);
+        let credentials = self
+            .auth
+            .send_with_code(&config, &arn, &source, |code| {
+                get_role_credentials(
+                    &client,
+                    &role_arn,
+                    &role_session_name,
+                    external_id.clone(),
+                    &arn,
+                    code,
+                    duration,
+                )
+            })
+            .await?;
+
+        save_auth_credentials(self.files.credentials(), &self.profile, &credentials)?;
 
         Ok(credentials)
     }
@@ -149,18 +1096,13 @@ impl CredentialsProvider for FileCredentialsProvider {
 
 /// Provider for authenticating to AWS with MFA using environment variables
 pub struct EnvCredentialsProvider {
-    code: String,
-    identifier: Option<String>,
-    duration: i32,
+    auth: AuthOptions,
+    client: ClientOptions,
 }
 
 impl EnvCredentialsProvider {
-    pub fn new(code: &str, identifier: Option<String>, duration: i32) -> Self {
-        Self {
-            code: String::from(code),
-            identifier,
-            duration,
-        }
+    pub fn new(auth: AuthOptions, client: ClientOptions) -> Self {
+        Self { auth, client }
     }
 }
 
@@ -182,12 +1124,781 @@ impl CredentialsProvider for EnvCredentialsProvider {
 
     /// Authenticate using [`aws_config::environment::EnvironmentVariableCredentialsProvider`]) and return new [`Credentials`]
     async fn authenticate(&self) -> Result<Credentials, Error> {
-        let config = get_env_config().await;
-        let provider = get_env_provider();
-        let client = get_client(&config, provider);
-        let arn = get_mfa_device_arn(&client, self.identifier.clone()).await?;
-        let credentials = get_auth_credentials(&client, &arn, &self.code, self.duration).await?;
+        let totp = self.auth.env_totp()?;
+        let settings = ProfileSettings::empty();
+        let source = self.auth.code_source(totp.as_ref(), &settings, None)?;
+        let duration = self.auth.duration(&settings)?;
+        duration.seconds(MAX_SESSION_DURATION)?;
+        let config = get_env_config(&self.client).await?;
+        let provider = SharedCredentialsProvider::new(get_env_provider());
+        let client = get_client(
+            &config,
+            self.client.endpoint_url.as_deref(),
+            provider.clone(),
+        )?;
+        let iam = get_iam_client(&config, provider);
+        let arn = self.auth.mfa_device_arn(&client, &iam, &settings).await?;
+        let duration = duration.seconds(max_session_duration(&client, duration).await?)?;
+        let credentials = self
+            .auth
+            .send_with_code(&config, &arn, &source, |code| {
+                get_auth_credentials(&client, &arn, code, duration)
+            })
+            .await?;
+
+        Ok(credentials)
+    }
+}
+
+/// Options for getting credentials of a federated user scoped by session policies
+pub struct FederationOptions {
+    name: String,
+    policy_file: Option<String>,
+    policy_arns: Vec<String>,
+    duration: Option<SessionDuration>,
+}
+
+impl FederationOptions {
+    pub fn new(
+        name: &str,
+        policy_file: Option<String>,
+        policy_arns: Vec<String>,
+        duration: Option<SessionDuration>,
+    ) -> Self {
+        Self {
+            name: String::from(name),
+            policy_file,
+            policy_arns,
+            duration,
+        }
+    }
+}
+
+/// Provider for getting federated user credentials with GetFederationToken, called with the credentials of a profile
+pub struct FederationCredentialsProvider {
+    federation: FederationOptions,
+    files: ProfileFiles,
+    client: ClientOptions,
+    caller: String,
+    profile: Option<String>,
+}
+
+impl FederationCredentialsProvider {
+    /// Call GetFederationToken with the long-term credentials of the `caller` profile, such as `{profile}-noauth`,
+    /// and save federated user credentials under `profile` in the credentials file when set
+    ///
+    /// STS refuses GetFederationToken with session credentials, such as a session saved by [`FileCredentialsProvider`].
+    pub fn new(
+        federation: FederationOptions,
+        files: ProfileFiles,
+        client: ClientOptions,
+        caller: &str,
+        profile: Option<String>,
+    ) -> Self {
+        Self {
+            federation,
+            files,
+            client,
+            caller: String::from(caller),
+            profile,
+        }
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for FederationCredentialsProvider {
+    /// Validate and return current [`Credentials`] from credentials file unless expired or not saved
+    async fn validate(&self) -> Result<Option<Credentials>, Error> {
+        if let Some(profile) = &self.profile {
+            if let Some(credentials) = find_auth_credentials(self.files.credentials(), profile)? {
+                if !credentials.expired() {
+                    return Ok(Some(credentials));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Get federated user credentials using [`aws_config::profile::ProfileFileCredentialsProvider`] for the caller profile and return new [`Credentials`]
+    ///
+    /// `duration_seconds` is read from the caller profile.
+    async fn authenticate(&self) -> Result<Credentials, Error> {
+        let settings = ProfileSettings::load(&self.files, from_ref(&self.caller))?;
+        let duration = SessionDuration::resolve(self.federation.duration, &settings)?
+            .seconds(MAX_SESSION_DURATION)?;
+        let policy = match &self.federation.policy_file {
+            Some(policy_file) => Some(read_policy_file(policy_file)?),
+            None => None,
+        };
+
+        let config = get_profile_config(&self.files, &self.client, &settings, &self.caller).await?;
+        let provider = get_profile_provider(&self.files, &self.caller);
+        let client = get_client(&config, self.client.endpoint_url.as_deref(), provider)?;
+        let credentials = get_federation_credentials(
+            &client,
+            &self.federation.name,
+            policy,
+            &self.federation.policy_arns,
+            duration,
+        )
+        .await?;
+
+        if let Some(profile) = &self.profile {
+            save_auth_credentials(self.files.credentials(), profile, &credentials)?;
+        }
 
         Ok(credentials)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use aws_config::retry::RetryConfig;
+    use aws_config::SdkConfig;
+    use aws_credential_types::Credentials;
+    use aws_sdk_iam::{Client as IamClient, Config as IamConfig};
+    use aws_sdk_sts::config::Region;
+    use aws_sdk_sts::{Client, Config};
+    use aws_smithy_http_client::test_util::{ReplayEvent, StaticReplayClient};
+    use aws_smithy_runtime_api::client::http::SharedHttpClient;
+    use aws_smithy_types::body::SdkBody;
+    use http::{Method, Request, Response};
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+    use std::time::Duration;
+
+    use crate::config::ProfileSettings;
+    use crate::error::Error::{
+        DurationOutOfRange, InvalidAccessKey, InvalidDuration, InvalidMfaCodeFormat,
+        MfaCodeAlreadyUsed, MissingCode,
+    };
+    use crate::state::UsedCodes;
+    use crate::sts::get_auth_credentials;
+    use crate::{
+        backoff, max_session_duration, AuthOptions, ClientOptions, CodeSource, CredentialsProvider,
+        FederationCredentialsProvider, FederationOptions, MfaCodeOptions, MfaCodePrompt,
+        ProfileFiles, RetryOptions, SessionDuration, TimeoutOptions,
+    };
+
+    fn get_session_token_event(code: &str, status: u16, body: &str) -> Result<ReplayEvent> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://sts.eu-west-1.amazonaws.com/")
+            .body(SdkBody::from(format!("Action=GetSessionToken&Version=2011-06-15&DurationSeconds=3600&SerialNumber=arn&TokenCode={code}")))?;
+        let response = Response::builder()
+            .status(status)
+            .body(SdkBody::from(String::from(body)))?;
+
+        Ok(ReplayEvent::new(request, response))
+    }
+
+    #[tokio::test]
+    async fn test_send_with_code() -> Result<()> {
+        let success = "
+        <GetSessionTokenResponse>
+            <GetSessionTokenResult>
+                <Credentials>
+                    <AccessKeyId>access_key_id</AccessKeyId>
+                    <SecretAccessKey>secret_access_key</SecretAccessKey>
+                    <SessionToken>session_token</SessionToken>
+                    <Expiration>2022-08-31T19:55:58Z</Expiration>
+                </Credentials>
+            </GetSessionTokenResult>
+        </GetSessionTokenResponse>";
+        let replay_client = StaticReplayClient::new(vec![
+            get_session_token_event("123456", 400, &error_response("Throttling"))?,
+            get_session_token_event("123456", 503, &error_response("ServiceUnavailable"))?,
+            get_session_token_event("654321", 200, success)?,
+        ]);
+        let conf = Config::builder()
+            .behavior_version_latest()
+            .region(Region::new("eu-west-1"))
+            .credentials_provider(Credentials::new("", "", None, None, ""))
+            .http_client(replay_client.clone())
+            .build();
+        let client = Client::from_conf(conf);
+        let config = SdkConfig::builder()
+            .retry_config(
+                RetryConfig::standard()
+                    .with_max_attempts(3)
+                    .with_initial_backoff(Duration::from_millis(1)),
+            )
+            .build();
+        let dir = temp_dir().join(format!(
+            "aws-mfa-test-send-with-code-{}",
+            std::process::id()
+        ));
+        let used_codes = dir.join("codes.json").to_string_lossy().to_string();
+        let auth = AuthOptions::new(
+            MfaCodeOptions::new(
+                Some(String::from("123456")),
+                None,
+                Some(|_| Some(String::from("654321"))),
+            ),
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(used_codes.clone()),
+        );
+        let source = auth.code_source(None, &ProfileSettings::empty(), None)?;
+
+        let credentials = auth
+            .send_with_code(&config, "arn", &source, |code| {
+                get_auth_credentials(&client, "arn", code, 3600)
+            })
+            .await?;
+
+        replay_client.assert_requests_match(&[]);
+        assert_eq!(credentials.access_key_id(), "access_key_id");
+        assert!(UsedCodes::new(&used_codes).contains("arn", "654321")?);
+
+        let replay_client = StaticReplayClient::new(vec![get_session_token_event(
+            "123456",
+            503,
+            &error_response("ServiceUnavailable"),
+        )?]);
+        let conf = Config::builder()
+            .behavior_version_latest()
+            .region(Region::new("eu-west-1"))
+            .credentials_provider(Credentials::new("", "", None, None, ""))
+            .http_client(replay_client.clone())
+            .build();
+        let client = Client::from_conf(conf);
+        let auth = |code: &str| {
+            AuthOptions::new(
+                MfaCodeOptions::new(Some(String::from(code)), None, None),
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(used_codes.clone()),
+            )
+        };
+        let source = auth("123456").code_source(None, &ProfileSettings::empty(), None)?;
+
+        let credentials = auth("123456")
+            .send_with_code(&config, "arn", &source, |code| {
+                get_auth_credentials(&client, "arn", code, 3600)
+            })
+            .await;
+
+        replay_client.assert_requests_match(&[]);
+        assert!(matches!(
+            credentials,
+            Err(crate::error::Error::ServiceUnavailable(_))
+        ));
+
+        let source = auth("654321").code_source(None, &ProfileSettings::empty(), None)?;
+        let credentials = auth("654321")
+            .send_with_code(&config, "arn", &source, |code| {
+                get_auth_credentials(&client, "arn", code, 3600)
+            })
+            .await;
+
+        assert!(matches!(credentials, Err(MfaCodeAlreadyUsed(_))));
+
+        remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_backoff() -> Result<()> {
+        let (initial, max) = (Duration::from_secs(1), Duration::from_secs(20));
+        assert_eq!(backoff(initial, max, 1), Duration::from_secs(1));
+        assert_eq!(backoff(initial, max, 3), Duration::from_secs(4));
+        assert_eq!(backoff(initial, max, 6), max);
+        assert_eq!(backoff(initial, max, 33), max);
+        assert_eq!(backoff(initial, max, u32::MAX), max);
+        assert_eq!(backoff(Duration::MAX, Duration::MAX, 64), Duration::MAX);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_code_source() -> Result<()> {
+        let auth = |code: Option<&str>, command: Option<&str>, prompt: Option<MfaCodePrompt>| {
+            AuthOptions::new(
+                MfaCodeOptions::new(code.map(String::from), command.map(String::from), prompt),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+        };
+        let given = |auth: AuthOptions| -> Result<String, crate::error::Error> {
+            match auth.code_source(None, &ProfileSettings::empty(), None)? {
+                CodeSource::Given(code, _) => Ok(code),
+                _ => panic!("expected a given code"),
+            }
+        };
+
+        assert_eq!(given(auth(Some("012345"), Some("exit 1"), None))?, "012345");
+        for code in ["12345", "1234567", "12345a", " 12345"] {
+            assert!(matches!(
+                given(auth(Some(code), None, None)),
+                Err(InvalidMfaCodeFormat)
+            ));
+        }
+
+        let prompt: MfaCodePrompt = |message| match message.starts_with("Invalid") {
+            true => Some(String::from("654321")),
+            false => Some(String::from("65432")),
+        };
+        assert_eq!(given(auth(None, None, Some(prompt)))?, "654321");
+        assert!(matches!(
+            given(auth(None, None, Some(|_| None))),
+            Err(MissingCode)
+        ));
+
+        #[cfg(unix)]
+        match auth(None, Some("echo 112233"), Some(prompt)).code_source(
+            None,
+            &ProfileSettings::empty(),
+            Some("default"),
+        )? {
+            CodeSource::Command(command) => assert_eq!(command.run("arn").await?, "112233"),
+            _ => panic!("expected a command"),
+        }
+
+        let dir = temp_dir().join(format!("aws-mfa-test-code-source-{}", std::process::id()));
+        create_dir_all(&dir)?;
+        let credentials = dir.join("credentials");
+        write(
+            &credentials,
+            "[default-noauth]\nmfa_seed = GEZDGNBVGY3TQOJQ\n",
+        )?;
+        #[cfg(unix)]
+        std::fs::set_permissions(
+            &credentials,
+            std::os::unix::fs::PermissionsExt::from_mode(0o600),
+        )?;
+        let files = ProfileFiles::new(
+            "",
+            Some(dir.join("config").to_string_lossy().to_string()),
+            Some(credentials.to_string_lossy().to_string()),
+            Some(dir.join("seeds.json").to_string_lossy().to_string()),
+        );
+        let profiles = [String::from("default-noauth")];
+
+        let totp = auth(None, None, None).totp(&files, &profiles)?;
+        assert!(totp.is_some());
+        assert!(auth(None, Some("exit 1"), None)
+            .totp(&files, &profiles)?
+            .is_none());
+        assert!(matches!(
+            auth(None, None, None).code_source(totp.as_ref(), &ProfileSettings::empty(), None)?,
+            CodeSource::Totp(_)
+        ));
+        assert!(matches!(
+            auth(None, Some("exit 1"), None).code_source(
+                totp.as_ref(),
+                &ProfileSettings::empty(),
+                None
+            )?,
+            CodeSource::Command(_)
+        ));
+
+        remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_code_source_with_locked_seed_store() -> Result<()> {
+        let dir = temp_dir().join(format!(
+            "aws-mfa-test-locked-seed-store-{}",
+            std::process::id()
+        ));
+        create_dir_all(&dir)?;
+        let config = dir.join("config");
+        let credentials = dir.join("credentials");
+        let seed_store = dir.join("seeds.json");
+        write(
+            &config,
+            "[profile default-noauth]\nmfa_code_command = echo 112233\n",
+        )?;
+        write(&credentials, "[default-noauth]\n")?;
+        std::fs::set_permissions(
+            &credentials,
+            std::os::unix::fs::PermissionsExt::from_mode(0o600),
+        )?;
+        write(
+            &seed_store,
+            r#"{"version": 1, "cipher": "chacha20poly1305", "nonce": "AAAAAAAAAAAAAAAA", "ciphertext": "AAAA",
+                "kdf": {"algorithm": "scrypt", "log_n": 4, "r": 8, "p": 1, "salt": "AAAA"}}"#,
+        )?;
+
+        let files = ProfileFiles::new(
+            "",
+            Some(config.to_string_lossy().to_string()),
+            Some(credentials.to_string_lossy().to_string()),
+            Some(seed_store.to_string_lossy().to_string()),
+        );
+        let profiles = [String::from("default-noauth")];
+        let settings = ProfileSettings::load(&files, &profiles)?;
+        let auth = AuthOptions::new(
+            MfaCodeOptions::new(None, None, None),
+            None,
+            None,
+            None,
+            None,
+            Some(|_| None),
+            None,
+        );
+
+        let totp = auth.totp(&files, &profiles)?;
+        assert!(totp.is_none());
+        match auth.code_source(totp.as_ref(), &settings, Some("default"))? {
+            CodeSource::Command(command) => assert_eq!(command.run("arn").await?, "112233"),
+            _ => panic!("expected a command"),
+        }
+
+        remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    fn get_caller_identity_event(caller: &str) -> Result<ReplayEvent> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://sts.eu-west-1.amazonaws.com/")
+            .body(SdkBody::from("Action=GetCallerIdentity&Version=2011-06-15"))?;
+        let response = Response::builder().status(200).body(SdkBody::from(format!(
+            "
+        <GetCallerIdentityResponse>
+            <GetCallerIdentityResult>
+                <UserId>user_id</UserId>
+                <Account>account</Account>
+                <Arn>{caller}</Arn>
+            </GetCallerIdentityResult>
+        </GetCallerIdentityResponse>"
+        )))?;
+
+        Ok(ReplayEvent::new(request, response))
+    }
+
+    const USER: &str = "arn:aws:iam::account:user/user_name";
+    const ROOT: &str = "arn:aws:iam::account:root";
+
+    const LIST_MFA_DEVICES: &str = "
+        <ListMFADevicesResponse>
+            <ListMFADevicesResult>
+                <IsTruncated>false</IsTruncated>
+                <MFADevices>
+                    <member>
+                        <UserName>user_name</UserName>
+                        <SerialNumber>arn:aws:iam::account:mfa/phone</SerialNumber>
+                        <EnableDate>2022-08-31T19:55:58Z</EnableDate>
+                    </member>
+                </MFADevices>
+            </ListMFADevicesResult>
+        </ListMFADevicesResponse>";
+
+    fn error_response(code: &str) -> String {
+        format!("<ErrorResponse><Error><Type>Sender</Type><Code>{code}</Code><Message>message</Message></Error></ErrorResponse>")
+    }
+
+    fn list_mfa_devices_event(status: u16, body: &str) -> Result<ReplayEvent> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://iam.amazonaws.com/")
+            .body(SdkBody::from("Action=ListMFADevices&Version=2010-05-08"))?;
+        let response = Response::builder()
+            .status(status)
+            .body(SdkBody::from(String::from(body)))?;
+
+        Ok(ReplayEvent::new(request, response))
+    }
+
+    fn get_clients(
+        sts_events: Vec<ReplayEvent>,
+        iam_events: Vec<ReplayEvent>,
+    ) -> (Client, IamClient, StaticReplayClient, StaticReplayClient) {
+        let sts_replay_client = StaticReplayClient::new(sts_events);
+        let iam_replay_client = StaticReplayClient::new(iam_events);
+        let sts_conf = Config::builder()
+            .behavior_version_latest()
+            .region(Region::new("eu-west-1"))
+            .credentials_provider(Credentials::new("", "", None, None, ""))
+            .http_client(sts_replay_client.clone())
+            .build();
+        let iam_conf = IamConfig::builder()
+            .behavior_version_latest()
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("", "", None, None, ""))
+            .http_client(iam_replay_client.clone())
+            .build();
+
+        (
+            Client::from_conf(sts_conf),
+            IamClient::from_conf(iam_conf),
+            sts_replay_client,
+            iam_replay_client,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_mfa_device_arn() -> Result<()> {
+        let dir = temp_dir().join(format!(
+            "aws-mfa-test-mfa-device-arn-{}",
+            std::process::id()
+        ));
+        create_dir_all(&dir)?;
+        let config = dir.join("config");
+        write(
+            &config,
+            "[profile default-noauth]\nmfa_serial = arn:aws:iam::account:mfa/configured\n",
+        )?;
+        let files = ProfileFiles::new(
+            &dir.to_string_lossy(),
+            Some(config.to_string_lossy().to_string()),
+            Some(dir.join("credentials").to_string_lossy().to_string()),
+            None,
+        );
+        let settings = ProfileSettings::load(&files, &[String::from("default-noauth")])?;
+        let auth = |identifier: Option<&str>, mfa_serial: Option<&str>| {
+            AuthOptions::new(
+                MfaCodeOptions::new(None, None, None),
+                identifier.map(String::from),
+                mfa_serial.map(String::from),
+                None,
+                None,
+                None,
+                None,
+            )
+        };
+
+        let (client, iam, sts_replay_client, _) = get_clients(vec![], vec![]);
+        let arn = auth(None, Some("arn:aws:iam::account:mfa/given"))
+            .mfa_device_arn(&client, &iam, &settings)
+            .await?;
+        assert_eq!(arn, "arn:aws:iam::account:mfa/given");
+        let arn = auth(None, None)
+            .mfa_device_arn(&client, &iam, &settings)
+            .await?;
+        assert_eq!(arn, "arn:aws:iam::account:mfa/configured");
+        sts_replay_client.assert_requests_match(&[]);
+
+        let (client, iam, sts_replay_client, _) =
+            get_clients(vec![get_caller_identity_event(USER)?], vec![]);
+        let arn = auth(Some("device_id"), None)
+            .mfa_device_arn(&client, &iam, &settings)
+            .await?;
+        sts_replay_client.assert_requests_match(&[]);
+        assert_eq!(arn, "arn:aws:iam::account:mfa/device_id");
+
+        let settings = ProfileSettings::load(&files, &[String::from("other-noauth")])?;
+        let (client, iam, _, iam_replay_client) =
+            get_clients(vec![], vec![list_mfa_devices_event(200, LIST_MFA_DEVICES)?]);
+        let arn = auth(None, None)
+            .mfa_device_arn(&client, &iam, &settings)
+            .await?;
+        iam_replay_client.assert_requests_match(&[]);
+        assert_eq!(arn, "arn:aws:iam::account:mfa/phone");
+        assert!(read_to_string(&config)?
+            .contains("[profile other-noauth]\nmfa_serial = arn:aws:iam::account:mfa/phone\n"));
+
+        let settings = ProfileSettings::empty();
+        let (client, iam, sts_replay_client, _) = get_clients(
+            vec![get_caller_identity_event(USER)?],
+            vec![list_mfa_devices_event(
+                403,
+                &error_response("AccessDenied"),
+            )?],
+        );
+        let arn = auth(None, None)
+            .mfa_device_arn(&client, &iam, &settings)
+            .await?;
+        sts_replay_client.assert_requests_match(&[]);
+        assert_eq!(arn, "arn:aws:iam::account:mfa/user_name");
+
+        let (client, iam, _, _) = get_clients(
+            vec![get_caller_identity_event(USER)?],
+            vec![list_mfa_devices_event(
+                403,
+                &error_response("InvalidClientTokenId"),
+            )?],
+        );
+        assert!(matches!(
+            auth(None, None)
+                .mfa_device_arn(&client, &iam, &settings)
+                .await,
+            Err(InvalidAccessKey)
+        ));
+
+        remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_federation_authenticate() -> Result<()> {
+        let dir = temp_dir().join(format!("aws-mfa-test-federation-{}", std::process::id()));
+        create_dir_all(&dir)?;
+        let config = dir.join("config");
+        let credentials = dir.join("credentials");
+        let policy = dir.join("policy.json");
+        write(
+            &config,
+            "[profile default-noauth]\nduration_seconds = 7200\n",
+        )?;
+        write(
+            &credentials,
+            "[default-noauth]\naws_access_key_id = long_term_access_key_id\naws_secret_access_key = long_term_secret_access_key\n",
+        )?;
+        #[cfg(unix)]
+        std::fs::set_permissions(
+            &credentials,
+            std::os::unix::fs::PermissionsExt::from_mode(0o600),
+        )?;
+        write(&policy, "{}")?;
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://sts.eu-west-1.amazonaws.com/")
+            .body(SdkBody::from("Action=GetFederationToken&Version=2011-06-15&Name=build&Policy=%7B%7D&DurationSeconds=7200"))?;
+        let response = Response::builder().status(200).body(SdkBody::from(
+            "
+        <GetFederationTokenResponse>
+            <GetFederationTokenResult>
+                <Credentials>
+                    <AccessKeyId>access_key_id</AccessKeyId>
+                    <SecretAccessKey>secret_access_key</SecretAccessKey>
+                    <SessionToken>session_token</SessionToken>
+                    <Expiration>2099-08-31T19:55:58Z</Expiration>
+                </Credentials>
+                <FederatedUser>
+                    <Arn>arn:aws:sts::account:federated-user/build</Arn>
+                    <FederatedUserId>account:build</FederatedUserId>
+                </FederatedUser>
+            </GetFederationTokenResult>
+        </GetFederationTokenResponse>",
+        ))?;
+        let replay_client = StaticReplayClient::new(vec![ReplayEvent::new(request, response)]);
+        let client = ClientOptions {
+            http_client: Some(SharedHttpClient::new(replay_client.clone())),
+            ..ClientOptions::new(
+                Some(String::from("eu-west-1")),
+                None,
+                None,
+                None,
+                TimeoutOptions::default(),
+                RetryOptions::default(),
+            )
+        };
+        let provider = FederationCredentialsProvider::new(
+            FederationOptions::new(
+                "build",
+                Some(policy.to_string_lossy().to_string()),
+                Vec::new(),
+                None,
+            ),
+            ProfileFiles::new(
+                &dir.to_string_lossy(),
+                Some(config.to_string_lossy().to_string()),
+                Some(credentials.to_string_lossy().to_string()),
+                None,
+            ),
+            client,
+            "default-noauth",
+            Some(String::from("build")),
+        );
+
+        assert!(provider.validate().await?.is_none());
+        let federated = provider.authenticate().await?;
+
+        replay_client.assert_requests_match(&[]);
+        let authorization = replay_client
+            .actual_requests()
+            .next()
+            .and_then(|r| r.headers().get("authorization"))
+            .map(String::from)
+            .unwrap_or_default();
+        assert!(authorization.contains("Credential=long_term_access_key_id/"));
+        assert_eq!(federated.access_key_id(), "access_key_id");
+        assert_eq!(
+            provider
+                .validate()
+                .await?
+                .map(|c| String::from(c.session_token())),
+            Some(String::from("session_token"))
+        );
+
+        remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parse_session_duration() -> Result<()> {
+        for (value, expected) in [
+            ("3600", SessionDuration::Seconds(3600)),
+            ("max", SessionDuration::Max),
+            ("MAX", SessionDuration::Max),
+            ("12h", SessionDuration::Seconds(43200)),
+            ("90m", SessionDuration::Seconds(5400)),
+            ("1h30m15s", SessionDuration::Seconds(5415)),
+        ] {
+            assert_eq!(value.parse::<SessionDuration>()?, expected);
+        }
+
+        for value in ["", "1h30", "1d", "h", "-1h", "99999999h"] {
+            assert!(matches!(
+                value.parse::<SessionDuration>(),
+                Err(InvalidDuration(v)) if v == value
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_session_duration_seconds() -> Result<()> {
+        let (client, _, sts_replay_client, _) = get_clients(vec![], vec![]);
+        assert_eq!(
+            max_session_duration(&client, SessionDuration::Seconds(3600)).await?,
+            129600
+        );
+        sts_replay_client.assert_requests_match(&[]);
+
+        let (client, _, sts_replay_client, _) = get_clients(
+            vec![
+                get_caller_identity_event(ROOT)?,
+                get_caller_identity_event(ROOT)?,
+                get_caller_identity_event(USER)?,
+            ],
+            vec![],
+        );
+        let root = max_session_duration(&client, SessionDuration::Max).await?;
+        assert_eq!(root, 3600);
+        assert_eq!(SessionDuration::Max.seconds(root)?, 3600);
+        let root = max_session_duration(&client, SessionDuration::Seconds(43200)).await?;
+        assert!(matches!(
+            SessionDuration::Seconds(43200).seconds(root),
+            Err(DurationOutOfRange {
+                duration: 43200,
+                min: 900,
+                max: 3600
+            })
+        ));
+        let user = max_session_duration(&client, SessionDuration::Seconds(43200)).await?;
+        assert_eq!(user, 129600);
+        assert_eq!(SessionDuration::Seconds(43200).seconds(user)?, 43200);
+        assert!(matches!(
+            SessionDuration::Seconds(600).seconds(user),
+            Err(DurationOutOfRange { duration: 600, .. })
+        ));
+        sts_replay_client.assert_requests_match(&[]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+use std::path::Path;
+
+use rqrr::PreparedImage;
+
+use crate::error::Error;
+use crate::error::Error::{InvalidMfaSetting, InvalidOtpAuthUri, MissingQrCode, ReadQrCodeError};
+use crate::totp::decode_seed;
+
+const OTPAUTH_SCHEME: &str = "otpauth://";
+const TOTP_TYPE: &str = "totp/";
+
+/// TOTP seed and settings of an MFA device, as enrolled with an `otpauth://totp/` URI
+pub struct OtpAuth {
+    pub seed: String,
+    pub issuer: Option<String>,
+    pub algorithm: Option<String>,
+    pub digits: Option<u32>,
+    pub period: Option<u64>,
+}
+
+impl OtpAuth {
+    fn from_seed(seed: &str) -> Result<Self, Error> {
+        decode_seed(seed)?;
+
+        Ok(Self {
+            seed: String::from(seed),
+            issuer: None,
+            algorithm: None,
+            digits: None,
+            period: None,
+        })
+    }
+}
+
+/// Decode `%XX` escapes of a URI component
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut chars = s.bytes();
+    while let Some(b) = chars.next() {
+        match b {
+            b'%' => {
+                let hex = [chars.next()?, chars.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            _ => bytes.push(b),
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+fn parse_number<T: std::str::FromStr>(key: &str, value: String) -> Result<T, Error> {
+    value.parse::<T>().map_err(|_| InvalidMfaSetting {
+        key: String::from(key),
+        value,
+    })
+}
+
+/// Parse an `otpauth://totp/<issuer>:<account>?secret=<seed>&issuer=...&algorithm=...&digits=...&period=...` URI
+///
+/// The `issuer` parameter takes precedence over the issuer prefix of the label.
+fn parse_uri(uri: &str) -> Result<OtpAuth, Error> {
+    let rest = uri
+        .get(..OTPAUTH_SCHEME.len())
+        .filter(|scheme| scheme.eq_ignore_ascii_case(OTPAUTH_SCHEME))
+        .and_then(|_| uri[OTPAUTH_SCHEME.len()..].get(..TOTP_TYPE.len()))
+        .filter(|otp_type| otp_type.eq_ignore_ascii_case(TOTP_TYPE))
+        .map(|_| &uri[OTPAUTH_SCHEME.len() + TOTP_TYPE.len()..])
+        .ok_or(InvalidOtpAuthUri)?;
+    let (label, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let label = percent_decode(label).ok_or(InvalidOtpAuthUri)?;
+    let mut issuer = label
+        .split_once(':')
+        .map(|(issuer, _)| String::from(issuer.trim()));
+
+    let mut otpauth = OtpAuth {
+        seed: String::new(),
+        issuer: None,
+        algorithm: None,
+        digits: None,
+        period: None,
+    };
+    for parameter in query.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+        let value = percent_decode(value).ok_or(InvalidOtpAuthUri)?;
+        match key.to_ascii_lowercase().as_str() {
+            "secret" => otpauth.seed = value,
+            "issuer" => issuer = Some(value),
+            "algorithm" => otpauth.algorithm = Some(value),
+            "digits" => otpauth.digits = Some(parse_number("mfa_digits", value)?),
+            "period" => otpauth.period = Some(parse_number("mfa_period", value)?),
+            _ => {}
+        }
+    }
+
+    if otpauth.seed.is_empty() {
+        return Err(InvalidOtpAuthUri);
+    }
+    decode_seed(&otpauth.seed)?;
+    otpauth.issuer = issuer.filter(|i| !i.is_empty());
+
+    Ok(otpauth)
+}
+
+/// Decode the content of the first QR code found in a PNG or JPEG image
+fn decode_qr_code(path: &str) -> Result<String, Error> {
+    let image = image::open(path)
+        .map_err(|e| ReadQrCodeError {
+            path: String::from(path),
+            source: e,
+        })?
+        .to_luma8();
+    let mut prepared = PreparedImage::prepare_from_greyscale(
+        image.width() as usize,
+        image.height() as usize,
+        |x, y| image.get_pixel(x as u32, y as u32).0[0],
+    );
+
+    prepared
+        .detect_grids()
+        .into_iter()
+        .find_map(|grid| grid.decode().ok().map(|(_, content)| content))
+        .ok_or_else(|| MissingQrCode(String::from(path)))
+}
+
+fn parse_content(content: &str) -> Result<OtpAuth, Error> {
+    let content = content.trim();
+    match content
+        .get(..OTPAUTH_SCHEME.len())
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case(OTPAUTH_SCHEME))
+    {
+        true => parse_uri(content),
+        false => OtpAuth::from_seed(content),
+    }
+}
+
+/// Read an MFA device from an `otpauth://totp/` URI, a Base32 seed, or the path of a PNG or JPEG image of its QR code
+pub fn read_otpauth(input: &str) -> Result<OtpAuth, Error> {
+    let input = input.trim();
+    match Path::new(input).is_file() {
+        true => parse_content(&decode_qr_code(input)?),
+        false => parse_content(input),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use image::{GrayImage, Luma};
+    use qrcode::{Color, QrCode};
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    use crate::error::Error::{InvalidMfaSeed, InvalidOtpAuthUri, MissingQrCode};
+    use crate::otpauth::read_otpauth;
+
+    const URI: &str = "otpauth://totp/Amazon%20Web%20Services:alice@123456789012?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=Amazon%20Web%20Services&algorithm=SHA256&digits=8&period=60";
+
+    fn write_qr_code(path: &std::path::Path, content: &str) -> Result<()> {
+        let code = QrCode::new(content.as_bytes())?;
+        let (width, scale, margin) = (code.width() as u32, 8, 4);
+        let colors = code.to_colors();
+        let image = GrayImage::from_fn(
+            (width + 2 * margin) * scale,
+            (width + 2 * margin) * scale,
+            |x, y| {
+                let (x, y) = (x / scale, y / scale);
+                let dark = (margin..width + margin).contains(&x)
+                    && (margin..width + margin).contains(&y)
+                    && colors[((y - margin) * width + x - margin) as usize] == Color::Dark;
+                Luma([if dark { 0 } else { 255 }])
+            },
+        );
+        image.save(path)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_otpauth() -> Result<()> {
+        let otpauth = read_otpauth(URI)?;
+        assert_eq!(otpauth.seed, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(otpauth.issuer.as_deref(), Some("Amazon Web Services"));
+        assert_eq!(otpauth.algorithm.as_deref(), Some("SHA256"));
+        assert_eq!(otpauth.digits, Some(8));
+        assert_eq!(otpauth.period, Some(60));
+
+        let otpauth = read_otpauth("otpauth://totp/Example:bob?secret=GEZDGNBVGY3TQOJQ")?;
+        assert_eq!(otpauth.issuer.as_deref(), Some("Example"));
+        assert!(otpauth.algorithm.is_none());
+
+        let otpauth = read_otpauth(" gezd gnbv gy3t qojq \n")?;
+        assert_eq!(otpauth.seed, "gezd gnbv gy3t qojq");
+        assert!(otpauth.issuer.is_none());
+
+        assert!(matches!(
+            read_otpauth("otpauth://hotp/Example:bob?secret=GEZDGNBVGY3TQOJQ&counter=1"),
+            Err(InvalidOtpAuthUri)
+        ));
+        assert!(matches!(
+            read_otpauth("otpauth://totp/Example:bob?issuer=Example"),
+            Err(InvalidOtpAuthUri)
+        ));
+        assert!(matches!(
+            read_otpauth("otpauth://totp/Example:bob?secret=not-base32!"),
+            Err(InvalidMfaSeed)
+        ));
+        assert!(
+            read_otpauth("otpauth://totp/Example:bob?secret=GEZDGNBVGY3TQOJQ&digits=six").is_err()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_otpauth_from_qr_code() -> Result<()> {
+        let dir = temp_dir().join(format!("aws-mfa-test-qr-code-{}", std::process::id()));
+        create_dir_all(&dir)?;
+
+        let png = dir.join("qr.png");
+        write_qr_code(&png, URI)?;
+        let otpauth = read_otpauth(&png.to_string_lossy())?;
+        assert_eq!(otpauth.seed, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(otpauth.digits, Some(8));
+
+        let jpeg = dir.join("qr.jpg");
+        write_qr_code(&jpeg, "GEZDGNBVGY3TQOJQ")?;
+        assert_eq!(
+            read_otpauth(&jpeg.to_string_lossy())?.seed,
+            "GEZDGNBVGY3TQOJQ"
+        );
+
+        let blank = dir.join("blank.png");
+        GrayImage::from_pixel(64, 64, Luma([255])).save(&blank)?;
+        assert!(matches!(
+            read_otpauth(&blank.to_string_lossy()),
+            Err(MissingQrCode(_))
+        ));
+
+        let text = dir.join("qr.txt");
+        write(&text, URI)?;
+        assert!(read_otpauth(&text.to_string_lossy()).is_err());
+
+        remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
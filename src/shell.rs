@@ -0,0 +1,357 @@
+use std::env::var;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use data_encoding::HEXLOWER;
+use rand_core::{OsRng, RngCore};
+use serde_json::{Map, Value};
+
+use crate::error::Error;
+use crate::error::Error::{
+    InvalidEnvFormat, MissingGithubEnv, UnsupportedCmdValue, WriteGithubEnvError,
+};
+
+const GITHUB_ENV: &str = "GITHUB_ENV";
+const SHELL: &str = "SHELL";
+
+/// Output format of environment variables, one per shell or tool loading them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EnvFormat {
+    Bash,
+    Zsh,
+    Fish,
+    PowerShell,
+    Nushell,
+    Cmd,
+    Dotenv,
+    Json,
+    GithubActions,
+}
+
+impl FromStr for EnvFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bash" | "sh" => Ok(Self::Bash),
+            "zsh" => Ok(Self::Zsh),
+            "fish" => Ok(Self::Fish),
+            "powershell" | "pwsh" => Ok(Self::PowerShell),
+            "nushell" | "nu" => Ok(Self::Nushell),
+            "cmd" => Ok(Self::Cmd),
+            "dotenv" => Ok(Self::Dotenv),
+            "json" => Ok(Self::Json),
+            "github-actions" => Ok(Self::GithubActions),
+            _ => Err(InvalidEnvFormat(String::from(s))),
+        }
+    }
+}
+
+impl EnvFormat {
+    /// Format of a shell from the name or path of its executable, like `-zsh` for a login shell or `pwsh.exe`
+    fn from_shell(shell: &str) -> Option<Self> {
+        let name = Path::new(shell.trim().trim_start_matches('-'))
+            .file_stem()?
+            .to_string_lossy()
+            .to_ascii_lowercase();
+
+        match name.as_str() {
+            "bash" | "sh" | "dash" | "ash" | "ksh" | "mksh" => Some(Self::Bash),
+            "zsh" => Some(Self::Zsh),
+            "fish" => Some(Self::Fish),
+            "pwsh" | "powershell" => Some(Self::PowerShell),
+            "nu" => Some(Self::Nushell),
+            "cmd" => Some(Self::Cmd),
+            _ => None,
+        }
+    }
+
+    /// Format of the parent shell, then of the `SHELL` environment variable, else bash or PowerShell on Windows
+    pub fn detect() -> Self {
+        parent_process()
+            .and_then(|name| Self::from_shell(&name))
+            .or_else(|| var(SHELL).ok().and_then(|shell| Self::from_shell(&shell)))
+            .unwrap_or(match cfg!(windows) {
+                true => Self::PowerShell,
+                false => Self::Bash,
+            })
+    }
+
+    /// Statements setting `vars` in this format, with values quoted and escaped for it
+    pub fn format(self, vars: &[(&str, String)]) -> Result<String, Error> {
+        match self {
+            Self::Json => {
+                let object = vars
+                    .iter()
+                    .map(|(name, value)| (String::from(*name), Value::from(value.as_str())))
+                    .collect::<Map<_, _>>();
+                Ok(format!("{:#}\n", Value::Object(object)))
+            }
+            Self::Nushell => {
+                let fields = vars
+                    .iter()
+                    .map(|(name, value)| format!("{name}: {}", double_quote(value)))
+                    .collect::<Vec<_>>();
+                Ok(format!("{{{}}}\n", fields.join(", ")))
+            }
+            _ => vars
+                .iter()
+                .map(|(name, value)| Ok(self.statement(name, value)? + "\n"))
+                .collect(),
+        }
+    }
+
+    fn statement(self, name: &str, value: &str) -> Result<String, Error> {
+        Ok(match self {
+            Self::Bash | Self::Zsh => format!("export {name}={}", single_quote(value)),
+            Self::Fish => format!(
+                "set -gx {name} '{}'",
+                value.replace('\\', "\\\\").replace('\'', "\\'")
+            ),
+            Self::PowerShell => format!("$Env:{name} = '{}'", value.replace('\'', "''")),
+            Self::Cmd => match value.contains(['"', '%', '\n', '\r']) {
+                true => return Err(UnsupportedCmdValue(String::from(name))),
+                false => format!("set \"{name}={value}\""),
+            },
+            Self::Dotenv => format!("{name}={}", double_quote(value).replace('$', "\\$")),
+            Self::GithubActions => match value.contains(['\n', '\r']) {
+                true => {
+                    let delimiter = format!("ghadelimiter_{}", random_hex());
+                    format!("{name}<<{delimiter}\n{value}\n{delimiter}")
+                }
+                false => format!("{name}={value}"),
+            },
+            Self::Json | Self::Nushell => unreachable!("formatted as a single object"),
+        })
+    }
+
+    /// Output to print for `names` to be unset, or set empty in formats loading values only
+    pub fn unset(self, names: &[&str]) -> Result<String, Error> {
+        let statement = |name: &str| match self {
+            Self::Bash | Self::Zsh => Some(format!("unset {name}\n")),
+            Self::Fish => Some(format!("set -e {name}\n")),
+            Self::PowerShell => Some(format!(
+                "Remove-Item Env:{name} -ErrorAction SilentlyContinue\n"
+            )),
+            Self::Cmd => Some(format!("set \"{name}=\"\n")),
+            _ => None,
+        };
+
+        match names.iter().map(|name| statement(name)).collect() {
+            Some(output) => Ok(output),
+            None => {
+                let vars = names
+                    .iter()
+                    .map(|name| (*name, String::new()))
+                    .collect::<Vec<_>>();
+                self.export(&vars)
+            }
+        }
+    }
+
+    /// Check that this format can be exported, since GitHub Actions requires the `GITHUB_ENV` file
+    pub fn validate(self) -> Result<Self, Error> {
+        if self == Self::GithubActions {
+            var(GITHUB_ENV).map_err(|_| MissingGithubEnv)?;
+        }
+
+        Ok(self)
+    }
+
+    /// Output to print for `vars` to be loaded, appending them to the `GITHUB_ENV` file for GitHub Actions
+    ///
+    /// For GitHub Actions, only commands masking the values in workflow logs are printed.
+    pub fn export(self, vars: &[(&str, String)]) -> Result<String, Error> {
+        if self != Self::GithubActions {
+            return self.format(vars);
+        }
+
+        let path = var(GITHUB_ENV).map_err(|_| MissingGithubEnv)?;
+        let content = self.format(vars)?;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .map_err(|e| WriteGithubEnvError { path, source: e })?;
+
+        Ok(vars
+            .iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(_, value)| format!("::add-mask::{value}\n"))
+            .collect())
+    }
+}
+
+/// Quote for POSIX shells, closing the quotes around each single quote
+fn single_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Quote with backslash escapes, as understood by dotenv files and nushell
+fn double_quote(value: &str) -> String {
+    Value::from(value).to_string()
+}
+
+fn random_hex() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+
+    HEXLOWER.encode(&bytes)
+}
+
+/// Name of the parent process executable
+#[cfg(unix)]
+fn parent_process() -> Option<String> {
+    let ppid = std::os::unix::process::parent_id();
+    if let Ok(name) = std::fs::read_to_string(format!("/proc/{ppid}/comm")) {
+        return Some(String::from(name.trim()));
+    }
+
+    let output = std::process::Command::new("ps")
+        .args(["-o", "comm=", "-p", &ppid.to_string()])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+
+    Some(String::from(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+#[cfg(not(unix))]
+fn parent_process() -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::error::Error::{InvalidEnvFormat, UnsupportedCmdValue};
+    use crate::shell::EnvFormat;
+
+    fn vars() -> Vec<(&'static str, String)> {
+        vec![
+            ("AWS_ACCESS_KEY_ID", String::from("AKIA")),
+            (
+                "AWS_SECRET_ACCESS_KEY",
+                String::from("it's \"$ecret\" 100%\\"),
+            ),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_parse_env_format() -> Result<()> {
+        assert_eq!("bash".parse::<EnvFormat>()?, EnvFormat::Bash);
+        assert_eq!("PowerShell".parse::<EnvFormat>()?, EnvFormat::PowerShell);
+        assert_eq!(
+            "github-actions".parse::<EnvFormat>()?,
+            EnvFormat::GithubActions
+        );
+        assert!(matches!(
+            "tcsh".parse::<EnvFormat>(),
+            Err(InvalidEnvFormat(_))
+        ));
+
+        assert_eq!(EnvFormat::from_shell("-zsh"), Some(EnvFormat::Zsh));
+        assert_eq!(
+            EnvFormat::from_shell("/usr/bin/fish"),
+            Some(EnvFormat::Fish)
+        );
+        assert_eq!(
+            EnvFormat::from_shell("pwsh.exe"),
+            Some(EnvFormat::PowerShell)
+        );
+        assert_eq!(EnvFormat::from_shell("nu"), Some(EnvFormat::Nushell));
+        assert_eq!(EnvFormat::from_shell("dash"), Some(EnvFormat::Bash));
+        assert_eq!(EnvFormat::from_shell("cargo"), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_format_env() -> Result<()> {
+        assert_eq!(
+            EnvFormat::Bash.format(&vars())?,
+            "export AWS_ACCESS_KEY_ID='AKIA'\nexport AWS_SECRET_ACCESS_KEY='it'\\''s \"$ecret\" 100%\\'\n"
+        );
+        assert_eq!(
+            EnvFormat::Fish.format(&vars())?,
+            "set -gx AWS_ACCESS_KEY_ID 'AKIA'\nset -gx AWS_SECRET_ACCESS_KEY 'it\\'s \"$ecret\" 100%\\\\'\n"
+        );
+        assert_eq!(
+            EnvFormat::PowerShell.format(&vars())?,
+            "$Env:AWS_ACCESS_KEY_ID = 'AKIA'\n$Env:AWS_SECRET_ACCESS_KEY = 'it''s \"$ecret\" 100%\\'\n"
+        );
+        assert_eq!(
+            EnvFormat::Nushell.format(&vars())?,
+            "{AWS_ACCESS_KEY_ID: \"AKIA\", AWS_SECRET_ACCESS_KEY: \"it's \\\"$ecret\\\" 100%\\\\\"}\n"
+        );
+        assert_eq!(
+            EnvFormat::Cmd.format(&[
+                ("AWS_ACCESS_KEY_ID", String::from("AKIA")),
+                ("AWS_SECRET_ACCESS_KEY", String::from("it's a&b|c^d\\")),
+            ])?,
+            "set \"AWS_ACCESS_KEY_ID=AKIA\"\nset \"AWS_SECRET_ACCESS_KEY=it's a&b|c^d\\\"\n"
+        );
+        assert!(matches!(
+            EnvFormat::Cmd.format(&vars()),
+            Err(UnsupportedCmdValue(name)) if name == "AWS_SECRET_ACCESS_KEY"
+        ));
+        assert_eq!(
+            EnvFormat::Dotenv.format(&vars())?,
+            "AWS_ACCESS_KEY_ID=\"AKIA\"\nAWS_SECRET_ACCESS_KEY=\"it's \\\"\\$ecret\\\" 100%\\\\\"\n"
+        );
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&EnvFormat::Json.format(&vars())?)?,
+            serde_json::json!({
+                "AWS_ACCESS_KEY_ID": "AKIA",
+                "AWS_SECRET_ACCESS_KEY": "it's \"$ecret\" 100%\\",
+            })
+        );
+
+        assert_eq!(
+            EnvFormat::GithubActions.format(&vars())?,
+            "AWS_ACCESS_KEY_ID=AKIA\nAWS_SECRET_ACCESS_KEY=it's \"$ecret\" 100%\\\n"
+        );
+        let multiline = EnvFormat::GithubActions.format(&[("NAME", String::from("a\nb"))])?;
+        let delimiter = multiline
+            .strip_prefix("NAME<<")
+            .and_then(|rest| rest.split_once('\n'))
+            .map(|(delimiter, _)| delimiter)
+            .unwrap_or_default();
+        assert!(delimiter.starts_with("ghadelimiter_"));
+        assert_eq!(multiline, format!("NAME<<{delimiter}\na\nb\n{delimiter}\n"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unset_env() -> Result<()> {
+        let names = ["AWS_MFA_KEY_FILE", "AWS_PROFILE"];
+
+        assert_eq!(
+            EnvFormat::Zsh.unset(&names)?,
+            "unset AWS_MFA_KEY_FILE\nunset AWS_PROFILE\n"
+        );
+        assert_eq!(
+            EnvFormat::Fish.unset(&names)?,
+            "set -e AWS_MFA_KEY_FILE\nset -e AWS_PROFILE\n"
+        );
+        assert_eq!(
+            EnvFormat::PowerShell.unset(&names[..1])?,
+            "Remove-Item Env:AWS_MFA_KEY_FILE -ErrorAction SilentlyContinue\n"
+        );
+        assert_eq!(
+            EnvFormat::Cmd.unset(&names[..1])?,
+            "set \"AWS_MFA_KEY_FILE=\"\n"
+        );
+        assert_eq!(
+            EnvFormat::Nushell.unset(&names[..1])?,
+            "{AWS_MFA_KEY_FILE: \"\"}\n"
+        );
+
+        Ok(())
+    }
+}
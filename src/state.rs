@@ -0,0 +1,115 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use data_encoding::HEXLOWER;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+use crate::io::{read_secret_file, write_secret_file};
+
+/// Time during which STS may still reject a code it consumed, covering the previous, current and next time steps
+const REUSE_WINDOW: Duration = Duration::from_secs(90);
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Hash of a code salted with its MFA device, so that the state file never contains codes
+fn hash(mfa_serial: &str, code: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(format!("{mfa_serial}:{code}")))
+}
+
+/// State file recording the last code used with each MFA device
+pub struct UsedCodes {
+    path: String,
+}
+
+impl UsedCodes {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: String::from(path),
+        }
+    }
+
+    fn load(&self) -> Result<Map<String, Value>, Error> {
+        Ok(read_secret_file(&self.path)?
+            .and_then(|content| serde_json::from_str::<Value>(&content).ok())
+            .and_then(|state| match state {
+                Value::Object(state) => Some(state),
+                _ => None,
+            })
+            .unwrap_or_default())
+    }
+
+    /// Whether `code` was used with the MFA device `mfa_serial` recently enough for STS to reject it
+    pub fn contains(&self, mfa_serial: &str, code: &str) -> Result<bool, Error> {
+        let state = self.load()?;
+        let Some(used) = state.get(mfa_serial) else {
+            return Ok(false);
+        };
+
+        Ok(used["hash"].as_str() == Some(&hash(mfa_serial, code))
+            && used["timestamp"]
+                .as_u64()
+                .is_some_and(|t| now().saturating_sub(t) < REUSE_WINDOW.as_secs()))
+    }
+
+    /// Record `code` as the last code used with the MFA device `mfa_serial`
+    pub fn record(&self, mfa_serial: &str, code: &str) -> Result<(), Error> {
+        let mut state = self.load()?;
+        state.insert(
+            String::from(mfa_serial),
+            json!({
+                "hash": hash(mfa_serial, code),
+                "timestamp": now(),
+            }),
+        );
+
+        write_secret_file(&self.path, &format!("{:#}\n", Value::Object(state)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde_json::json;
+    use std::env::temp_dir;
+    use std::fs::{read_to_string, remove_dir_all, write};
+
+    use crate::state::{hash, now, UsedCodes};
+
+    #[tokio::test]
+    async fn test_used_codes() -> Result<()> {
+        let dir = temp_dir().join(format!("aws-mfa-test-used-codes-{}", std::process::id()));
+        let path = dir.join("codes.json");
+        let used_codes = UsedCodes::new(&path.to_string_lossy());
+
+        assert!(!used_codes.contains("arn:aws:iam::123456789012:mfa/device", "123456")?);
+
+        used_codes.record("arn:aws:iam::123456789012:mfa/device", "123456")?;
+        used_codes.record("arn:aws:iam::123456789012:mfa/other", "654321")?;
+        assert!(used_codes.contains("arn:aws:iam::123456789012:mfa/device", "123456")?);
+        assert!(!used_codes.contains("arn:aws:iam::123456789012:mfa/device", "654321")?);
+        assert!(!used_codes.contains("arn:aws:iam::123456789012:mfa/other", "123456")?);
+        assert!(!read_to_string(&path)?.contains("123456\""));
+
+        let expired = json!({
+            "arn:aws:iam::123456789012:mfa/device": {
+                "hash": hash("arn:aws:iam::123456789012:mfa/device", "123456"),
+                "timestamp": now() - 90,
+            },
+        });
+        write(&path, expired.to_string())?;
+        assert!(!used_codes.contains("arn:aws:iam::123456789012:mfa/device", "123456")?);
+
+        write(&path, "not json")?;
+        assert!(!used_codes.contains("arn:aws:iam::123456789012:mfa/device", "123456")?);
+
+        remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,569 @@
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use data_encoding::BASE64;
+use scrypt::{scrypt, Params};
+use serde_json::{json, Map, Value};
+use std::env::var;
+use std::path::Path;
+
+use crate::config::{MFA_ALGORITHM, MFA_DIGITS, MFA_PERIOD, MFA_SEED, MFA_SERIAL};
+use crate::error::Error;
+use crate::error::Error::{
+    DeriveKeyError, InvalidKeyFile, InvalidSeedStore, MissingPassphrase, MissingSeedStore,
+    PassphraseMismatch, WrongPassphrase,
+};
+use crate::io::{
+    lock_secret_file, read_secret_file, remove_secret_file, write_locked_secret_file,
+    write_secret_file,
+};
+use crate::totp::{get_totp, Totp};
+use crate::PassphrasePrompt;
+
+const VERSION: u64 = 1;
+const CIPHER: &str = "chacha20poly1305";
+const KDF: &str = "scrypt";
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+const SALT_LENGTH: usize = 16;
+const KEY_LENGTH: usize = 32;
+const MFA_ISSUER: &str = "mfa_issuer";
+
+pub const AWS_MFA_KEY_FILE: &str = "AWS_MFA_KEY_FILE";
+
+/// Parameters of the scrypt key derivation, saved next to the encrypted entries
+struct Kdf {
+    log_n: u8,
+    r: u32,
+    p: u32,
+    salt: Vec<u8>,
+}
+
+impl Kdf {
+    fn generate(log_n: u8) -> Self {
+        let mut salt = vec![0; SALT_LENGTH];
+        OsRng.fill_bytes(&mut salt);
+
+        Self {
+            log_n,
+            r: SCRYPT_R,
+            p: SCRYPT_P,
+            salt,
+        }
+    }
+
+    fn derive(&self, passphrase: &str) -> Result<Vec<u8>, Error> {
+        let params =
+            Params::new(self.log_n, self.r, self.p, KEY_LENGTH).map_err(|_| DeriveKeyError)?;
+        let mut key = vec![0; KEY_LENGTH];
+        scrypt(passphrase.as_bytes(), &self.salt, &params, &mut key).map_err(|_| DeriveKeyError)?;
+
+        Ok(key)
+    }
+}
+
+/// Decrypted entries of the seed store, with the key to encrypt them again
+struct Unlocked {
+    kdf: Kdf,
+    key: Vec<u8>,
+    entries: Map<String, Value>,
+}
+
+/// MFA seed of a profile in the seed store, with the settings of its TOTP generator
+#[derive(Clone)]
+pub struct SeedEntry {
+    profile: String,
+    settings: Map<String, Value>,
+}
+
+impl SeedEntry {
+    pub fn new(
+        profile: &str,
+        seed: &str,
+        algorithm: Option<String>,
+        digits: Option<u32>,
+        period: Option<u64>,
+        mfa_serial: Option<String>,
+        issuer: Option<String>,
+    ) -> Self {
+        let settings = [
+            (MFA_SEED, Some(String::from(seed))),
+            (MFA_ALGORITHM, algorithm),
+            (MFA_DIGITS, digits.map(|d| d.to_string())),
+            (MFA_PERIOD, period.map(|p| p.to_string())),
+            (MFA_SERIAL, mfa_serial),
+            (MFA_ISSUER, issuer),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.map(|v| (String::from(key), Value::String(v))))
+        .collect();
+
+        Self {
+            profile: String::from(profile),
+            settings,
+        }
+    }
+
+    /// Entry of a profile with the seed and TOTP settings of `get`, kept as they are written in profile settings
+    pub(crate) fn from_settings(profile: &str, get: impl Fn(&str) -> Option<String>) -> Self {
+        let settings = [MFA_SEED, MFA_ALGORITHM, MFA_DIGITS, MFA_PERIOD]
+            .into_iter()
+            .filter_map(|key| get(key).map(|v| (String::from(key), Value::String(v))))
+            .collect();
+
+        Self {
+            profile: String::from(profile),
+            settings,
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.settings.get(key).and_then(Value::as_str)
+    }
+
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    pub fn algorithm(&self) -> Option<&str> {
+        self.get(MFA_ALGORITHM)
+    }
+
+    pub fn digits(&self) -> Option<&str> {
+        self.get(MFA_DIGITS)
+    }
+
+    pub fn period(&self) -> Option<&str> {
+        self.get(MFA_PERIOD)
+    }
+
+    pub fn mfa_serial(&self) -> Option<&str> {
+        self.get(MFA_SERIAL)
+    }
+
+    pub fn issuer(&self) -> Option<&str> {
+        self.get(MFA_ISSUER)
+    }
+
+    fn totp(&self) -> Result<Option<Totp>, Error> {
+        get_totp(|key| self.get(key).map(String::from))
+    }
+}
+
+/// Store of MFA seeds encrypted with ChaCha20-Poly1305 and a key derived from a passphrase with scrypt
+pub struct SeedStore {
+    path: String,
+    prompt: Option<PassphrasePrompt>,
+    log_n: u8,
+}
+
+impl SeedStore {
+    pub fn new(path: &str, prompt: Option<PassphrasePrompt>) -> Self {
+        Self {
+            path: String::from(path),
+            prompt,
+            log_n: SCRYPT_LOG_N,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn exists(&self) -> bool {
+        Path::new(&self.path).exists()
+    }
+
+    fn passphrase(&self, message: &str) -> Result<String, Error> {
+        self.prompt
+            .and_then(|prompt| prompt(message))
+            .ok_or_else(|| MissingPassphrase(self.path.clone()))
+    }
+
+    fn new_passphrase(&self) -> Result<String, Error> {
+        let passphrase = self.passphrase("New passphrase for seed store")?;
+        if self.passphrase("Confirm passphrase")? != passphrase {
+            return Err(PassphraseMismatch);
+        }
+
+        Ok(passphrase)
+    }
+
+    /// Decrypt the seed store with the key cached in `AWS_MFA_KEY_FILE`, else with a prompted passphrase
+    fn open(&self) -> Result<Option<Unlocked>, Error> {
+        let Some(content) = read_secret_file(&self.path)? else {
+            return Ok(None);
+        };
+        let invalid = || InvalidSeedStore(self.path.clone());
+        let store = serde_json::from_str::<Value>(&content).map_err(|_| invalid())?;
+        let (kdf, nonce, ciphertext) = parse_store(&store).ok_or_else(invalid)?;
+
+        if let Some(key) = cached_key(&kdf) {
+            if let Some(entries) = decrypt(&key, &nonce, &ciphertext) {
+                return Ok(Some(Unlocked { kdf, key, entries }));
+            }
+        }
+
+        let key = kdf.derive(&self.passphrase("Passphrase for seed store")?)?;
+        let entries =
+            decrypt(&key, &nonce, &ciphertext).ok_or_else(|| WrongPassphrase(self.path.clone()))?;
+
+        Ok(Some(Unlocked { kdf, key, entries }))
+    }
+
+    fn open_or_create(&self) -> Result<Unlocked, Error> {
+        match self.open()? {
+            Some(unlocked) => Ok(unlocked),
+            None => {
+                let kdf = Kdf::generate(self.log_n);
+                let key = kdf.derive(&self.new_passphrase()?)?;
+
+                Ok(Unlocked {
+                    kdf,
+                    key,
+                    entries: Map::new(),
+                })
+            }
+        }
+    }
+
+    /// Encrypt and write the entries, with the seed store locked by the caller since it was opened
+    fn save(&self, unlocked: &Unlocked) -> Result<(), Error> {
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&unlocked.key));
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let plaintext = Value::Object(unlocked.entries.clone()).to_string();
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| InvalidSeedStore(self.path.clone()))?;
+
+        let store = json!({
+            "version": VERSION,
+            "kdf": {
+                "algorithm": KDF,
+                "log_n": unlocked.kdf.log_n,
+                "r": unlocked.kdf.r,
+                "p": unlocked.kdf.p,
+                "salt": BASE64.encode(&unlocked.kdf.salt),
+            },
+            "cipher": CIPHER,
+            "nonce": BASE64.encode(&nonce),
+            "ciphertext": BASE64.encode(&ciphertext),
+        });
+
+        write_locked_secret_file(&self.path, &format!("{store:#}\n"))
+    }
+
+    /// List entries of the seed store, which is empty when missing
+    pub fn entries(&self) -> Result<Vec<SeedEntry>, Error> {
+        let entries = match self.open()? {
+            Some(unlocked) => unlocked.entries,
+            None => return Ok(Vec::new()),
+        };
+
+        Ok(entries
+            .into_iter()
+            .filter_map(|(profile, settings)| match settings {
+                Value::Object(settings) => Some(SeedEntry { profile, settings }),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Add or replace the entry of a profile, creating the seed store with a new passphrase when missing
+    pub fn add(&self, entry: SeedEntry) -> Result<(), Error> {
+        entry.totp()?;
+
+        let _lock = lock_secret_file(&self.path)?;
+        let mut unlocked = self.open_or_create()?;
+        unlocked
+            .entries
+            .insert(entry.profile, Value::Object(entry.settings));
+
+        self.save(&unlocked)
+    }
+
+    /// Add the entries of profiles without one, creating the seed store with a new passphrase when missing, and return their profiles
+    pub fn add_missing(&self, entries: Vec<SeedEntry>) -> Result<Vec<String>, Error> {
+        for entry in &entries {
+            entry.totp()?;
+        }
+
+        let _lock = lock_secret_file(&self.path)?;
+        let mut unlocked = self.open_or_create()?;
+        let mut added = Vec::new();
+        for entry in entries {
+            if !unlocked.entries.contains_key(&entry.profile) {
+                added.push(entry.profile.clone());
+                unlocked
+                    .entries
+                    .insert(entry.profile, Value::Object(entry.settings));
+            }
+        }
+
+        if !added.is_empty() {
+            self.save(&unlocked)?;
+        }
+
+        Ok(added)
+    }
+
+    /// Remove the entry of a profile, returning whether it existed
+    pub fn remove(&self, profile: &str) -> Result<bool, Error> {
+        let _lock = lock_secret_file(&self.path)?;
+        let mut unlocked = self
+            .open()?
+            .ok_or_else(|| MissingSeedStore(self.path.clone()))?;
+        if unlocked.entries.remove(profile).is_none() {
+            return Ok(false);
+        }
+
+        self.save(&unlocked)?;
+
+        Ok(true)
+    }
+
+    /// Encrypt the seed store again with a key derived from a new passphrase and salt
+    pub fn rekey(&self) -> Result<(), Error> {
+        let _lock = lock_secret_file(&self.path)?;
+        let mut unlocked = self
+            .open()?
+            .ok_or_else(|| MissingSeedStore(self.path.clone()))?;
+        unlocked.kdf = Kdf::generate(self.log_n);
+        unlocked.key = unlocked.kdf.derive(&self.new_passphrase()?)?;
+
+        self.save(&unlocked)
+    }
+
+    /// Cache the key of the seed store in `key_file`, to be exported as `AWS_MFA_KEY_FILE` for the shell session
+    pub fn unlock(&self, key_file: &str) -> Result<(), Error> {
+        let unlocked = self
+            .open()?
+            .ok_or_else(|| MissingSeedStore(self.path.clone()))?;
+        let cache = json!({
+            "salt": BASE64.encode(&unlocked.kdf.salt),
+            "key": BASE64.encode(&unlocked.key),
+        });
+
+        write_secret_file(key_file, &cache.to_string())
+    }
+
+    /// Delete the key of the seed store cached in `key_file`, returning whether it was cached
+    pub fn lock(&self, key_file: &str) -> Result<bool, Error> {
+        let Some(content) = read_secret_file(key_file)? else {
+            return Ok(false);
+        };
+        if parse_key_cache(&content).is_none() {
+            return Err(InvalidKeyFile(String::from(key_file)));
+        }
+
+        remove_secret_file(key_file)
+    }
+
+    /// TOTP generator of the first of `profiles` with an entry, unlocking the seed store only when it exists
+    ///
+    /// A seed store that cannot be unlocked without a passphrase prompt holds no seed, so that other code sources are used.
+    pub(crate) fn totp(&self, profiles: &[String]) -> Result<Option<Totp>, Error> {
+        if !self.exists() {
+            return Ok(None);
+        }
+
+        let entries = match self.entries() {
+            Err(MissingPassphrase(_)) => return Ok(None),
+            entries => entries?,
+        };
+        match profiles
+            .iter()
+            .find_map(|p| entries.iter().find(|e| &e.profile == p))
+        {
+            Some(entry) => entry.totp(),
+            None => Ok(None),
+        }
+    }
+}
+
+fn parse_store(store: &Value) -> Option<(Kdf, Vec<u8>, Vec<u8>)> {
+    let decode = |value: &Value| BASE64.decode(value.as_str()?.as_bytes()).ok();
+    if store["version"].as_u64()? != VERSION
+        || store["cipher"].as_str()? != CIPHER
+        || store["kdf"]["algorithm"].as_str()? != KDF
+    {
+        return None;
+    }
+
+    let kdf = Kdf {
+        log_n: u8::try_from(store["kdf"]["log_n"].as_u64()?).ok()?,
+        r: u32::try_from(store["kdf"]["r"].as_u64()?).ok()?,
+        p: u32::try_from(store["kdf"]["p"].as_u64()?).ok()?,
+        salt: decode(&store["kdf"]["salt"])?,
+    };
+
+    Some((kdf, decode(&store["nonce"])?, decode(&store["ciphertext"])?))
+}
+
+fn decrypt(key: &[u8], nonce: &[u8], ciphertext: &[u8]) -> Option<Map<String, Value>> {
+    if key.len() != KEY_LENGTH || nonce.len() != 12 {
+        return None;
+    }
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let plaintext = cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()?;
+
+    match serde_json::from_slice::<Value>(&plaintext).ok()? {
+        Value::Object(entries) => Some(entries),
+        _ => None,
+    }
+}
+
+/// Salt and key of a key file written by [`SeedStore::unlock`]
+fn parse_key_cache(content: &str) -> Option<(Vec<u8>, Vec<u8>)> {
+    let cache = serde_json::from_str::<Value>(content).ok()?;
+    let decode = |value: &Value| BASE64.decode(value.as_str()?.as_bytes()).ok();
+
+    Some((decode(&cache["salt"])?, decode(&cache["key"])?))
+}
+
+/// Key cached in the file of `AWS_MFA_KEY_FILE`, if it was derived with the salt of the seed store
+fn cached_key(kdf: &Kdf) -> Option<Vec<u8>> {
+    let content = read_secret_file(&var(AWS_MFA_KEY_FILE).ok()?).ok()??;
+    let (salt, key) = parse_key_cache(&content)?;
+
+    (salt == kdf.salt).then_some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use std::env::temp_dir;
+    use std::fs::{read_to_string, remove_dir_all};
+
+    use crate::error::Error::{InvalidKeyFile, PassphraseMismatch, WrongPassphrase};
+    use crate::store::{SeedEntry, SeedStore};
+
+    fn store(path: &str, prompt: fn(&str) -> Option<String>) -> SeedStore {
+        SeedStore {
+            path: String::from(path),
+            prompt: Some(prompt),
+            log_n: 4,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_seed_store() -> Result<()> {
+        let dir = temp_dir().join(format!("aws-mfa-test-seed-store-{}", std::process::id()));
+        let path = dir.join("seeds.json").to_string_lossy().to_string();
+
+        let seed_store = store(&path, |_| Some(String::from("passphrase")));
+        assert!(seed_store.entries()?.is_empty());
+        assert!(seed_store.totp(&[String::from("profile-1")])?.is_none());
+
+        seed_store.add(SeedEntry::new(
+            "profile-1",
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ",
+            None,
+            Some(8),
+            None,
+            Some(String::from("arn:aws:iam::123456789012:mfa/device_id")),
+            None,
+        ))?;
+        seed_store.add(SeedEntry::new(
+            "profile-2",
+            "GEZDGNBVGY3TQOJQ",
+            Some(String::from("SHA256")),
+            None,
+            Some(60),
+            None,
+            Some(String::from("Example")),
+        ))?;
+        assert!(seed_store
+            .add(SeedEntry::new(
+                "profile-3",
+                "invalid!",
+                None,
+                None,
+                None,
+                None,
+                None,
+            ))
+            .is_err());
+
+        let content = read_to_string(&path)?;
+        assert!(!content.contains("GEZDGNBVGY3TQOJQ"));
+        assert!(!content.contains("profile-1"));
+
+        let entries = seed_store.entries()?;
+        assert_eq!(
+            entries.iter().map(|e| e.profile()).collect::<Vec<_>>(),
+            vec!["profile-1", "profile-2"]
+        );
+        assert_eq!(entries[0].digits(), Some("8"));
+        assert_eq!(
+            entries[0].mfa_serial(),
+            Some("arn:aws:iam::123456789012:mfa/device_id")
+        );
+        assert_eq!(entries[1].algorithm(), Some("SHA256"));
+        assert_eq!(entries[1].issuer(), Some("Example"));
+
+        let totp = seed_store
+            .totp(&[String::from("profile-0"), String::from("profile-1")])?
+            .expect("totp");
+        assert_eq!(totp.generate(59)?, "94287082");
+
+        assert!(matches!(
+            store(&path, |_| Some(String::from("wrong"))).entries(),
+            Err(WrongPassphrase(_))
+        ));
+        assert!(store(&path, |_| None)
+            .totp(&[String::from("profile-1")])?
+            .is_none());
+
+        let migrated = seed_store.add_missing(vec![
+            SeedEntry::from_settings("profile-1", |key| {
+                (key == "mfa_seed").then(|| String::from("GEZDGNBV"))
+            }),
+            SeedEntry::from_settings("profile-4", |key| match key {
+                "mfa_seed" => Some(String::from("GEZDGNBVGY3TQOJQ")),
+                "mfa_digits" => Some(String::from("8")),
+                _ => None,
+            }),
+        ])?;
+        assert_eq!(migrated, vec!["profile-4"]);
+        let entries = seed_store.entries()?;
+        assert_eq!(entries[0].digits(), Some("8"));
+        assert_eq!(entries[2].profile(), "profile-4");
+        assert_eq!(entries[2].digits(), Some("8"));
+        assert!(seed_store.remove("profile-4")?);
+
+        assert!(seed_store.remove("profile-2")?);
+        assert!(!seed_store.remove("profile-2")?);
+
+        let rekeyed_store = store(&path, |message| match message {
+            "Passphrase for seed store" => Some(String::from("passphrase")),
+            _ => Some(String::from("new passphrase")),
+        });
+        rekeyed_store.rekey()?;
+        assert_eq!(
+            store(&path, |_| Some(String::from("new passphrase")))
+                .entries()?
+                .len(),
+            1
+        );
+
+        let mismatched_store = store(&path, |message| match message {
+            "Confirm passphrase" => Some(String::from("other passphrase")),
+            _ => Some(String::from("new passphrase")),
+        });
+        assert!(matches!(mismatched_store.rekey(), Err(PassphraseMismatch)));
+
+        let key_file = dir.join("seeds.key").to_string_lossy().to_string();
+        let rekeyed_store = store(&path, |_| Some(String::from("new passphrase")));
+        rekeyed_store.unlock(&key_file)?;
+        assert!(matches!(rekeyed_store.lock(&path), Err(InvalidKeyFile(_))));
+        assert!(rekeyed_store.lock(&key_file)?);
+        assert!(!rekeyed_store.lock(&key_file)?);
+
+        remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
@@ -1,50 +1,79 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use aws_config::SdkConfig;
 use aws_credential_types::provider::ProvideCredentials;
+use aws_sdk_sts::config::retry::RetryConfig;
 use aws_sdk_sts::config::Builder;
+use aws_sdk_sts::types::PolicyDescriptorType;
 use aws_sdk_sts::Client;
 
+use crate::arn::Arn;
 use crate::error::Error;
 use crate::error::Error::{
-    GetCallerIdentityError, GetSessionTokenError, InvalidIdentity, InvalidSession, Other,
+    AssumeRoleError, GetCallerIdentityError, GetFederationTokenError, GetSessionTokenError,
+    InvalidIdentity, InvalidSession, MissingRegion, UnsupportedPrincipal,
 };
 use crate::Credentials;
 
-pub fn get_client(config: &SdkConfig, provider: impl ProvideCredentials + 'static) -> Client {
-    let builder = Builder::from(config).credentials_provider(provider);
+const ROOT_MFA_DEVICE: &str = "root-account-mfa-device";
 
-    Client::from_conf(builder.build())
+pub fn get_client(
+    config: &SdkConfig,
+    endpoint_url: Option<&str>,
+    provider: impl ProvideCredentials + 'static,
+) -> Result<Client, Error> {
+    if config.region().is_none() {
+        return Err(MissingRegion);
+    }
+
+    let mut builder = Builder::from(config).credentials_provider(provider);
+    if let Some(endpoint_url) = endpoint_url {
+        builder = builder.endpoint_url(endpoint_url);
+    }
+
+    Ok(Client::from_conf(builder.build()))
 }
 
-pub async fn get_mfa_device_arn(
-    client: &Client,
-    identifier: Option<String>,
-) -> Result<String, Error> {
+/// Config sending requests carrying an MFA code once, leaving retries to the caller
+fn without_retries() -> Builder {
+    Builder::default().retry_config(RetryConfig::disabled())
+}
+
+pub async fn get_caller_arn(client: &Client) -> Result<String, Error> {
     let identity = client
         .get_caller_identity()
         .send()
         .await
-        .map_err(|e| GetCallerIdentityError(Box::new(e)))?;
-
-    let account = identity
-        .account()
-        .ok_or_else(|| InvalidIdentity(String::from("account")))?;
+        .map_err(|e| Error::from_sdk(e, GetCallerIdentityError))?;
 
-    let arn = identity
+    identity
         .arn()
-        .ok_or_else(|| InvalidIdentity(String::from("arn")))?;
+        .map(String::from)
+        .ok_or_else(|| InvalidIdentity(String::from("arn")))
+}
 
-    let user = arn
-        .split('/')
-        .next_back()
-        .ok_or_else(|| Other(anyhow!("could not extract user in arn `{}`", arn)))?;
+pub async fn get_mfa_device_arn(
+    client: &Client,
+    identifier: Option<String>,
+) -> Result<String, Error> {
+    let arn = get_caller_arn(client).await?;
+    let caller = arn.parse::<Arn>()?;
+
+    let device = match (caller.service(), caller.resource().split_once('/')) {
+        _ if caller.is_root() => ROOT_MFA_DEVICE,
+        ("iam", Some(("user", user))) => user.rsplit('/').next().unwrap_or(user),
+        _ => return Err(UnsupportedPrincipal(arn)),
+    };
 
     let identifier = match identifier {
         Some(i) => i,
-        None => String::from(user),
+        None => String::from(device),
     };
 
-    let arn = format!("arn:aws:iam::{account}:mfa/{identifier}");
+    let arn = format!(
+        "arn:{}:iam::{}:mfa/{identifier}",
+        caller.partition(),
+        caller.account()
+    );
 
     Ok(arn)
 }
@@ -52,7 +81,7 @@ pub async fn get_mfa_device_arn(
 pub async fn get_auth_credentials(
     client: &Client,
     arn: &str,
-    code: &str,
+    code: impl Into<String>,
     duration: i32,
 ) -> Result<Credentials, Error> {
     let session = client
@@ -60,9 +89,11 @@ pub async fn get_auth_credentials(
         .serial_number(arn)
         .token_code(code)
         .duration_seconds(duration)
+        .customize()
+        .config_override(without_retries())
         .send()
         .await
-        .map_err(|e| GetSessionTokenError(Box::new(e)))?;
+        .map_err(|e| Error::from_sdk(e, GetSessionTokenError))?;
 
     let credentials = session
         .credentials()
@@ -76,9 +107,79 @@ pub async fn get_auth_credentials(
     ))
 }
 
+pub async fn get_role_credentials(
+    client: &Client,
+    role_arn: &str,
+    role_session_name: &str,
+    external_id: Option<String>,
+    arn: &str,
+    code: impl Into<String>,
+    duration: i32,
+) -> Result<Credentials, Error> {
+    let role = client
+        .assume_role()
+        .role_arn(role_arn)
+        .role_session_name(role_session_name)
+        .set_external_id(external_id)
+        .serial_number(arn)
+        .token_code(code)
+        .duration_seconds(duration)
+        .customize()
+        .config_override(without_retries())
+        .send()
+        .await
+        .map_err(|e| Error::from_sdk(e, AssumeRoleError))?;
+
+    let credentials = role
+        .credentials()
+        .ok_or_else(|| InvalidSession(String::from("credentials")))?;
+
+    Ok(Credentials::new(
+        credentials.access_key_id(),
+        credentials.secret_access_key(),
+        credentials.session_token(),
+        credentials.expiration().secs(),
+    ))
+}
+
+pub async fn get_federation_credentials(
+    client: &Client,
+    name: &str,
+    policy: Option<String>,
+    policy_arns: &[String],
+    duration: i32,
+) -> Result<Credentials, Error> {
+    let policy_arns = policy_arns
+        .iter()
+        .map(|arn| PolicyDescriptorType::builder().arn(arn).build())
+        .collect::<Vec<_>>();
+
+    let federation = client
+        .get_federation_token()
+        .name(name)
+        .set_policy(policy)
+        .set_policy_arns((!policy_arns.is_empty()).then_some(policy_arns))
+        .duration_seconds(duration)
+        .send()
+        .await
+        .map_err(|e| Error::from_sdk(e, GetFederationTokenError))?;
+
+    let credentials = federation
+        .credentials()
+        .ok_or_else(|| InvalidSession(String::from("credentials")))?;
+
+    Ok(Credentials::new(
+        credentials.access_key_id(),
+        credentials.secret_access_key(),
+        credentials.session_token(),
+        credentials.expiration().secs(),
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
+    use aws_config::BehaviorVersion;
     use aws_credential_types::Credentials;
     use aws_sdk_sts::config::Region;
     use aws_sdk_sts::{Client, Config};
@@ -88,7 +189,15 @@ mod tests {
     use aws_smithy_types::DateTime;
     use http::{Method, Request, Response};
 
-    use crate::sts::{get_auth_credentials, get_mfa_device_arn};
+    use crate::error::Error;
+    use crate::error::Error::{
+        AccessDenied, ClockSkew, GetSessionTokenError, InvalidAccessKey, InvalidMfaCode,
+        InvalidSecretKey, MfaDeviceNotFound, UnsupportedPrincipal,
+    };
+    use crate::sts::{
+        get_auth_credentials, get_caller_arn, get_client, get_federation_credentials,
+        get_mfa_device_arn, get_role_credentials,
+    };
 
     #[tokio::test]
     async fn test_get_mfa_device_arn_without_identifier() -> Result<()> {
@@ -200,4 +309,344 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_get_role_credentials() -> Result<()> {
+        let credentials = Credentials::new("", "", None, None, "");
+        let duration = 900;
+        let role_arn = "role_arn";
+        let role_session_name = "role_session_name";
+        let external_id = "external_id";
+        let arn = "arn";
+        let code = "code";
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://sts.eu-west-1.amazonaws.com/")
+            .body(SdkBody::from(format!("Action=AssumeRole&Version=2011-06-15&RoleArn={}&RoleSessionName={}&DurationSeconds={}&ExternalId={}&SerialNumber={}&TokenCode={}", role_arn, role_session_name, duration, external_id, arn, code)))?;
+        let response = Response::builder().status(200).body(SdkBody::from(
+            "
+        <AssumeRoleResponse>
+            <AssumeRoleResult>
+                <Credentials>
+                    <AccessKeyId>access_key_id</AccessKeyId>
+                    <SecretAccessKey>secret_access_key</SecretAccessKey>
+                    <SessionToken>session_token</SessionToken>
+                    <Expiration>2022-08-31T19:55:58Z</Expiration>
+                </Credentials>
+            </AssumeRoleResult>
+        </AssumeRoleResponse>",
+        ))?;
+        let replay_client = StaticReplayClient::new(vec![ReplayEvent::new(request, response)]);
+        let conf = Config::builder()
+            .behavior_version_latest()
+            .region(Region::new("eu-west-1"))
+            .credentials_provider(credentials)
+            .http_client(replay_client.clone())
+            .build();
+        let client = Client::from_conf(conf);
+        let credentials = get_role_credentials(
+            &client,
+            role_arn,
+            role_session_name,
+            Some(String::from(external_id)),
+            arn,
+            code,
+            duration,
+        )
+        .await?;
+
+        replay_client.assert_requests_match(&[]);
+        assert_eq!(credentials.access_key_id(), "access_key_id");
+        assert_eq!(credentials.secret_access_key(), "secret_access_key");
+        assert_eq!(credentials.session_token(), "session_token");
+        assert_eq!(
+            credentials.session_expiration_timestamp(),
+            DateTime::from_str("2022-08-31T19:55:58Z", Format::DateTime)?.secs()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_federation_credentials() -> Result<()> {
+        let credentials = Credentials::new("", "", None, None, "");
+        let duration = 900;
+        let name = "name";
+        let policy_arn = "arn:aws:iam::aws:policy/ReadOnlyAccess";
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("https://sts.eu-west-1.amazonaws.com/")
+            .body(SdkBody::from(format!("Action=GetFederationToken&Version=2011-06-15&Name={}&Policy=%7B%7D&PolicyArns.member.1.arn={}&DurationSeconds={}", name, policy_arn.replace(':', "%3A").replace('/', "%2F"), duration)))?;
+        let response = Response::builder().status(200).body(SdkBody::from(
+            "
+        <GetFederationTokenResponse>
+            <GetFederationTokenResult>
+                <Credentials>
+                    <AccessKeyId>access_key_id</AccessKeyId>
+                    <SecretAccessKey>secret_access_key</SecretAccessKey>
+                    <SessionToken>session_token</SessionToken>
+                    <Expiration>2022-08-31T19:55:58Z</Expiration>
+                </Credentials>
+                <FederatedUser>
+                    <Arn>arn:aws:sts::account:federated-user/name</Arn>
+                    <FederatedUserId>account:name</FederatedUserId>
+                </FederatedUser>
+            </GetFederationTokenResult>
+        </GetFederationTokenResponse>",
+        ))?;
+        let replay_client = StaticReplayClient::new(vec![ReplayEvent::new(request, response)]);
+        let conf = Config::builder()
+            .behavior_version_latest()
+            .region(Region::new("eu-west-1"))
+            .credentials_provider(credentials)
+            .http_client(replay_client.clone())
+            .build();
+        let client = Client::from_conf(conf);
+        let credentials = get_federation_credentials(
+            &client,
+            name,
+            Some(String::from("{}")),
+            &[String::from(policy_arn)],
+            duration,
+        )
+        .await?;
+
+        replay_client.assert_requests_match(&[]);
+        assert_eq!(credentials.access_key_id(), "access_key_id");
+        assert_eq!(credentials.secret_access_key(), "secret_access_key");
+        assert_eq!(credentials.session_token(), "session_token");
+        assert_eq!(
+            credentials.session_expiration_timestamp(),
+            DateTime::from_str("2022-08-31T19:55:58Z", Format::DateTime)?.secs()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_mfa_device_arn_in_partitions() -> Result<()> {
+        for (partition, region, uri) in [
+            ("aws", "eu-west-1", "https://sts.eu-west-1.amazonaws.com/"),
+            (
+                "aws-cn",
+                "cn-north-1",
+                "https://sts.cn-north-1.amazonaws.com.cn/",
+            ),
+            (
+                "aws-us-gov",
+                "us-gov-west-1",
+                "https://sts.us-gov-west-1.amazonaws.com/",
+            ),
+        ] {
+            let credentials = Credentials::new("", "", None, None, "");
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri(uri)
+                .body(SdkBody::from("Action=GetCallerIdentity&Version=2011-06-15"))?;
+            let response = Response::builder().status(200).body(SdkBody::from(format!(
+                "
+        <GetCallerIdentityResponse>
+            <GetCallerIdentityResult>
+                <UserId>user_id</UserId>
+                <Account>account</Account>
+                <Arn>arn:{partition}:iam::account:user/path/user_name</Arn>
+            </GetCallerIdentityResult>
+        </GetCallerIdentityResponse>"
+            )))?;
+            let replay_client = StaticReplayClient::new(vec![ReplayEvent::new(request, response)]);
+            let conf = Config::builder()
+                .behavior_version_latest()
+                .region(Region::new(region))
+                .credentials_provider(credentials)
+                .http_client(replay_client.clone())
+                .build();
+            let client = Client::from_conf(conf);
+            let arn = get_mfa_device_arn(&client, None).await?;
+
+            replay_client.assert_requests_match(&[]);
+            assert_eq!(arn, format!("arn:{partition}:iam::account:mfa/user_name"));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_mfa_device_arn_for_principals() -> Result<()> {
+        for (caller, expected_arn) in [
+            (
+                "arn:aws:iam::account:root",
+                Some("arn:aws:iam::account:mfa/root-account-mfa-device"),
+            ),
+            (
+                "arn:aws:iam::account:user/user_name",
+                Some("arn:aws:iam::account:mfa/user_name"),
+            ),
+            (
+                "arn:aws:sts::account:assumed-role/role_name/session_name",
+                None,
+            ),
+            ("arn:aws:sts::account:federated-user/user_name", None),
+        ] {
+            let credentials = Credentials::new("", "", None, None, "");
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri("https://sts.eu-west-1.amazonaws.com/")
+                .body(SdkBody::from("Action=GetCallerIdentity&Version=2011-06-15"))?;
+            let response = Response::builder().status(200).body(SdkBody::from(format!(
+                "
+        <GetCallerIdentityResponse>
+            <GetCallerIdentityResult>
+                <UserId>user_id</UserId>
+                <Account>account</Account>
+                <Arn>{caller}</Arn>
+            </GetCallerIdentityResult>
+        </GetCallerIdentityResponse>"
+            )))?;
+            let replay_client = StaticReplayClient::new(vec![ReplayEvent::new(request, response)]);
+            let conf = Config::builder()
+                .behavior_version_latest()
+                .region(Region::new("eu-west-1"))
+                .credentials_provider(credentials)
+                .http_client(replay_client.clone())
+                .build();
+            let client = Client::from_conf(conf);
+            let arn = get_mfa_device_arn(&client, None).await;
+
+            replay_client.assert_requests_match(&[]);
+            match expected_arn {
+                Some(expected_arn) => assert_eq!(arn?, expected_arn),
+                None => assert!(matches!(arn, Err(UnsupportedPrincipal(a)) if a == caller)),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_client_without_region() -> Result<()> {
+        let config = aws_config::SdkConfig::builder().build();
+        let client = get_client(&config, None, Credentials::new("", "", None, None, ""));
+
+        assert!(matches!(client, Err(Error::MissingRegion)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_client_with_endpoint() -> Result<()> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("http://localhost:4566/")
+            .body(SdkBody::from("Action=GetCallerIdentity&Version=2011-06-15"))?;
+        let response = Response::builder().status(200).body(SdkBody::from(
+            "
+        <GetCallerIdentityResponse>
+            <GetCallerIdentityResult>
+                <UserId>user_id</UserId>
+                <Account>account</Account>
+                <Arn>arn:aws:iam::account:user/user_name</Arn>
+            </GetCallerIdentityResult>
+        </GetCallerIdentityResponse>",
+        ))?;
+        let replay_client = StaticReplayClient::new(vec![ReplayEvent::new(request, response)]);
+        let config = aws_config::SdkConfig::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("eu-west-1"))
+            .http_client(replay_client.clone())
+            .build();
+        let client = get_client(
+            &config,
+            Some("http://localhost:4566"),
+            Credentials::new("", "", None, None, ""),
+        )?;
+        get_caller_arn(&client).await?;
+
+        replay_client.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    type IsExpected = fn(&Error) -> bool;
+
+    #[tokio::test]
+    async fn test_get_auth_credentials_errors() -> Result<()> {
+        let cases: [(u16, &str, &str, IsExpected); 7] = [
+            (
+                403,
+                "AccessDenied",
+                "MultiFactorAuthentication failed with invalid MFA one time pass code. ",
+                |e| matches!(e, InvalidMfaCode),
+            ),
+            (
+                403,
+                "AccessDenied",
+                "MultiFactorAuthentication failed, unable to validate MFA code. Please verify your MFA serial number is valid and associated with this user.",
+                |e| matches!(e, MfaDeviceNotFound),
+            ),
+            (
+                403,
+                "AccessDenied",
+                "User is not authorized to perform: sts:GetSessionToken",
+                |e| matches!(e, AccessDenied(_)),
+            ),
+            (
+                403,
+                "InvalidClientTokenId",
+                "The security token included in the request is invalid.",
+                |e| matches!(e, InvalidAccessKey),
+            ),
+            (
+                403,
+                "SignatureDoesNotMatch",
+                "The request signature we calculated does not match the signature you provided.",
+                |e| matches!(e, InvalidSecretKey),
+            ),
+            (
+                403,
+                "SignatureDoesNotMatch",
+                "Signature expired: 20220831T195558Z is now earlier than 20220831T200058Z",
+                |e| matches!(e, ClockSkew),
+            ),
+            (400, "ValidationError", "Invalid duration", |e| {
+                matches!(e, GetSessionTokenError(_))
+            }),
+        ];
+        for (status, code, message, expected) in cases {
+            let credentials = Credentials::new("", "", None, None, "");
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri("https://sts.eu-west-1.amazonaws.com/")
+                .body(SdkBody::from("Action=GetSessionToken&Version=2011-06-15&DurationSeconds=3600&SerialNumber=arn&TokenCode=code"))?;
+            let response = Response::builder()
+                .status(status)
+                .body(SdkBody::from(format!(
+                    "
+        <ErrorResponse>
+            <Error>
+                <Type>Sender</Type>
+                <Code>{code}</Code>
+                <Message>{message}</Message>
+            </Error>
+            <RequestId>request_id</RequestId>
+        </ErrorResponse>"
+                )))?;
+            let replay_client = StaticReplayClient::new(vec![ReplayEvent::new(request, response)]);
+            let conf = Config::builder()
+                .behavior_version_latest()
+                .region(Region::new("eu-west-1"))
+                .credentials_provider(credentials)
+                .http_client(replay_client.clone())
+                .build();
+            let client = Client::from_conf(conf);
+            let error = get_auth_credentials(&client, "arn", "code", 3600).await;
+
+            replay_client.assert_requests_match(&[]);
+            match error {
+                Err(e) => assert!(expected(&e), "{code}: unexpected error {e:?}"),
+                Ok(_) => panic!("{code}: expected an error"),
+            }
+        }
+
+        Ok(())
+    }
 }
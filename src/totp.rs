@@ -0,0 +1,218 @@
+use data_encoding::BASE32_NOPAD;
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{MFA_ALGORITHM, MFA_DIGITS, MFA_PERIOD, MFA_SEED};
+use crate::error::Error;
+use crate::error::Error::{InvalidMfaSeed, InvalidMfaSetting};
+
+const DEFAULT_DIGITS: u32 = 6;
+const DEFAULT_PERIOD: u64 = 30;
+
+/// HMAC algorithm of a TOTP generator
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl FromStr for Algorithm {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().replace('-', "").as_str() {
+            "SHA1" => Ok(Self::Sha1),
+            "SHA256" => Ok(Self::Sha256),
+            "SHA512" => Ok(Self::Sha512),
+            _ => Err(()),
+        }
+    }
+}
+
+/// RFC 6238 time-based one-time password generator
+pub struct Totp {
+    secret: Vec<u8>,
+    algorithm: Algorithm,
+    digits: u32,
+    period: u64,
+}
+
+impl Totp {
+    pub fn new(secret: Vec<u8>, algorithm: Algorithm, digits: u32, period: u64) -> Self {
+        Self {
+            secret,
+            algorithm,
+            digits,
+            period,
+        }
+    }
+
+    /// Code for the time step containing `timestamp`, in seconds since the Unix epoch
+    pub fn generate(&self, timestamp: u64) -> Result<String, Error> {
+        let counter = (timestamp / self.period).to_be_bytes();
+        let hash = match self.algorithm {
+            Algorithm::Sha1 => sign::<Hmac<Sha1>>(&self.secret, &counter)?,
+            Algorithm::Sha256 => sign::<Hmac<Sha256>>(&self.secret, &counter)?,
+            Algorithm::Sha512 => sign::<Hmac<Sha512>>(&self.secret, &counter)?,
+        };
+
+        let offset = usize::from(hash[hash.len() - 1] & 0x0f);
+        let binary = u32::from_be_bytes([
+            hash[offset],
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]) & 0x7fff_ffff;
+
+        Ok(format!(
+            "{:0width$}",
+            binary % 10u32.pow(self.digits),
+            width = self.digits as usize
+        ))
+    }
+
+    /// Current code and the number of seconds it remains valid for
+    pub fn now(&self) -> Result<(String, u64), Error> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        Ok((
+            self.generate(timestamp)?,
+            self.period - timestamp % self.period,
+        ))
+    }
+}
+
+fn sign<M: Mac + KeyInit>(key: &[u8], message: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut mac = <M as KeyInit>::new_from_slice(key).map_err(|_| InvalidMfaSeed)?;
+    mac.update(message);
+
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// Decode a Base32 seed, ignoring case, whitespace, dashes and padding
+pub fn decode_seed(seed: &str) -> Result<Vec<u8>, Error> {
+    let seed = seed
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-' && *c != '=')
+        .map(|c| c.to_ascii_uppercase())
+        .collect::<String>();
+
+    match BASE32_NOPAD.decode(seed.as_bytes()) {
+        Ok(secret) if !secret.is_empty() => Ok(secret),
+        _ => Err(InvalidMfaSeed),
+    }
+}
+
+fn parse<T: FromStr>(
+    get: &impl Fn(&str) -> Option<String>,
+    key: &str,
+    default: T,
+    valid: impl Fn(&T) -> bool,
+) -> Result<T, Error> {
+    match get(key) {
+        Some(value) => value
+            .parse::<T>()
+            .ok()
+            .filter(valid)
+            .ok_or_else(|| InvalidMfaSetting {
+                key: String::from(key),
+                value,
+            }),
+        None => Ok(default),
+    }
+}
+
+/// Generator configured with `mfa_seed`, `mfa_algorithm`, `mfa_digits` and `mfa_period`, if a seed is set
+pub fn get_totp(get: impl Fn(&str) -> Option<String>) -> Result<Option<Totp>, Error> {
+    let Some(seed) = get(MFA_SEED) else {
+        return Ok(None);
+    };
+
+    let secret = decode_seed(&seed)?;
+    let algorithm = parse(&get, MFA_ALGORITHM, Algorithm::Sha1, |_| true)?;
+    let digits = parse(&get, MFA_DIGITS, DEFAULT_DIGITS, |d| matches!(d, 6 | 8))?;
+    let period = parse(&get, MFA_PERIOD, DEFAULT_PERIOD, |p| *p > 0)?;
+
+    Ok(Some(Totp::new(secret, algorithm, digits, period)))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::totp::{decode_seed, get_totp, Algorithm, Totp};
+
+    #[tokio::test]
+    async fn test_generate_rfc_6238_vectors() -> Result<()> {
+        let sha1 = Totp::new(b"12345678901234567890".to_vec(), Algorithm::Sha1, 8, 30);
+        let sha256 = Totp::new(
+            b"12345678901234567890123456789012".to_vec(),
+            Algorithm::Sha256,
+            8,
+            30,
+        );
+        let sha512 = Totp::new(
+            b"1234567890123456789012345678901234567890123456789012345678901234".to_vec(),
+            Algorithm::Sha512,
+            8,
+            30,
+        );
+
+        for (timestamp, expected_sha1, expected_sha256, expected_sha512) in [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ] {
+            assert_eq!(sha1.generate(timestamp)?, expected_sha1);
+            assert_eq!(sha256.generate(timestamp)?, expected_sha256);
+            assert_eq!(sha512.generate(timestamp)?, expected_sha512);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_totp() -> Result<()> {
+        assert!(get_totp(|_| None)?.is_none());
+
+        let totp = get_totp(|key| match key {
+            "mfa_seed" => Some(String::from("gezd gnbv gy3t qojq gezd gnbv gy3t qojq")),
+            _ => None,
+        })?
+        .expect("totp");
+        assert_eq!(totp.generate(59)?, "287082");
+        assert_eq!(totp.now()?.0.len(), 6);
+
+        let totp = get_totp(|key| match key {
+            "mfa_seed" => Some(String::from("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ")),
+            "mfa_algorithm" => Some(String::from("sha256")),
+            "mfa_digits" => Some(String::from("8")),
+            "mfa_period" => Some(String::from("60")),
+            _ => None,
+        })?
+        .expect("totp");
+        assert_eq!(totp.generate(59)?, totp.generate(0)?);
+        assert!(totp.now()?.1 <= 60);
+
+        assert!(get_totp(|key| match key {
+            "mfa_seed" => Some(String::from("GEZDGNBVGY3TQOJQ")),
+            "mfa_digits" => Some(String::from("7")),
+            _ => None,
+        })
+        .is_err());
+        assert!(decode_seed("not base32!").is_err());
+
+        Ok(())
+    }
+}